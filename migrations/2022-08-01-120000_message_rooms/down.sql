@@ -0,0 +1 @@
+ALTER TABLE message DROP COLUMN room
\ No newline at end of file
@@ -0,0 +1 @@
+ALTER TABLE message ADD COLUMN room VARCHAR NOT NULL DEFAULT 'general'
\ No newline at end of file
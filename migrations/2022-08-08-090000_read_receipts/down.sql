@@ -0,0 +1 @@
+DROP TABLE read_receipt
\ No newline at end of file
@@ -0,0 +1,6 @@
+CREATE TABLE read_receipt (
+  user_name VARCHAR NOT NULL,
+  room VARCHAR NOT NULL,
+  last_read_message_id INTEGER NOT NULL,
+  PRIMARY KEY (user_name, room)
+)
\ No newline at end of file
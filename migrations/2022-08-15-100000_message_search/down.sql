@@ -0,0 +1 @@
+DROP INDEX message_body_search
\ No newline at end of file
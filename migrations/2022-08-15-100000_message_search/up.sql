@@ -0,0 +1 @@
+CREATE INDEX message_body_search ON message USING GIN (to_tsvector('english', body))
\ No newline at end of file
@@ -0,0 +1,2 @@
+DROP INDEX message_created_at;
+ALTER TABLE message DROP COLUMN created_at
\ No newline at end of file
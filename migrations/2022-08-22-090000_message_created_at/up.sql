@@ -0,0 +1,2 @@
+ALTER TABLE message ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now();
+CREATE INDEX message_created_at ON message (created_at)
\ No newline at end of file
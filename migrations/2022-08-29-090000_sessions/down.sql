@@ -0,0 +1 @@
+DROP TABLE wiggles_session
\ No newline at end of file
@@ -0,0 +1,8 @@
+-- "remember me" logins, only a hash of each token is stored
+CREATE TABLE wiggles_session (
+  token_hash VARCHAR PRIMARY KEY,
+  user_email VARCHAR NOT NULL REFERENCES wiggles_user (email) ON DELETE CASCADE,
+  expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX wiggles_session_user_email ON wiggles_session (user_email)
\ No newline at end of file
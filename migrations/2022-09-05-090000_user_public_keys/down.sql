@@ -0,0 +1 @@
+ALTER TABLE wiggles_user DROP COLUMN public_key
\ No newline at end of file
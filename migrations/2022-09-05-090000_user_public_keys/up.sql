@@ -0,0 +1,2 @@
+-- X25519 key others encrypt direct messages to, the private half never leaves the user's machine
+ALTER TABLE wiggles_user ADD COLUMN public_key VARCHAR
\ No newline at end of file
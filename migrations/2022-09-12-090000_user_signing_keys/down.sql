@@ -0,0 +1 @@
+ALTER TABLE wiggles_user DROP COLUMN signing_key
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- Ed25519 key others check this user's events against, derived from the same secret as public_key
+ALTER TABLE wiggles_user ADD COLUMN signing_key VARCHAR
\ No newline at end of file
@@ -0,0 +1 @@
+ALTER TABLE message DROP COLUMN author_email
\ No newline at end of file
@@ -0,0 +1,5 @@
+-- Author of each message, set by the HTTP service from the logged in user rather than by the client.
+-- NULL only for messages older than this column whose name matches no user.
+ALTER TABLE message ADD COLUMN author_email VARCHAR REFERENCES wiggles_user (email) ON UPDATE CASCADE ON DELETE SET NULL;
+UPDATE message SET author_email = wiggles_user.email FROM wiggles_user WHERE message.name = wiggles_user.name;
+CREATE INDEX message_author_email ON message (author_email)
\ No newline at end of file
@@ -0,0 +1 @@
+DROP INDEX wiggles_user_name
\ No newline at end of file
@@ -0,0 +1,5 @@
+-- Names identify authors in events and transcripts, so no two users may share one.
+-- Existing duplicates keep the oldest user's name, the others get their id appended.
+UPDATE wiggles_user SET name = name || '-' || id
+WHERE id NOT IN (SELECT MIN(id) FROM wiggles_user GROUP BY name);
+CREATE UNIQUE INDEX wiggles_user_name ON wiggles_user (name)
\ No newline at end of file
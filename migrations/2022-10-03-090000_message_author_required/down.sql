@@ -0,0 +1,7 @@
+ALTER TABLE message ADD COLUMN name VARCHAR;
+UPDATE message SET name = wiggles_user.name FROM wiggles_user WHERE message.author_email = wiggles_user.email;
+ALTER TABLE message ALTER COLUMN name SET NOT NULL;
+ALTER TABLE message DROP CONSTRAINT message_author_email_fkey;
+ALTER TABLE message ADD CONSTRAINT message_author_email_fkey
+  FOREIGN KEY (author_email) REFERENCES wiggles_user (email) ON UPDATE CASCADE ON DELETE SET NULL;
+ALTER TABLE message ALTER COLUMN author_email DROP NOT NULL
\ No newline at end of file
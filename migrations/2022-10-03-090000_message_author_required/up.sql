@@ -0,0 +1,14 @@
+-- The author's user is who wrote a message, their name is joined from it.
+-- Authors no user is left for get a placeholder user without a password, nobody can log in as it.
+INSERT INTO wiggles_user (name, password, email)
+SELECT DISTINCT message.name, '', message.name || '@imported.invalid'
+FROM message
+WHERE message.author_email IS NULL
+  AND NOT EXISTS (SELECT 1 FROM wiggles_user WHERE wiggles_user.name = message.name);
+UPDATE message SET author_email = wiggles_user.email FROM wiggles_user
+WHERE message.author_email IS NULL AND message.name = wiggles_user.name;
+ALTER TABLE message ALTER COLUMN author_email SET NOT NULL;
+ALTER TABLE message DROP CONSTRAINT message_author_email_fkey;
+ALTER TABLE message ADD CONSTRAINT message_author_email_fkey
+  FOREIGN KEY (author_email) REFERENCES wiggles_user (email) ON UPDATE CASCADE;
+ALTER TABLE message DROP COLUMN name
\ No newline at end of file
@@ -0,0 +1,162 @@
+use crate::error_handler::CustomError;
+use crate::model::models::Message;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use zeroize::Zeroizing;
+
+/// `POST /sessions` body, `user` is a name or an email.
+/// Clients borrow the password, so sending it leaves no copy behind.
+#[derive(Serialize, Deserialize)]
+pub struct LoginRequest<'a> {
+    pub user: Cow<'a, str>,
+    pub password: Cow<'a, str>,
+    /// Long lived "remember me" session instead of one for this run
+    #[serde(default)]
+    pub remember: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LoginResponse {
+    pub token: String,
+    pub name: String,
+    pub email: String,
+}
+
+/// `POST /messages` body. The author is whoever the session belongs to.
+#[derive(Serialize, Deserialize)]
+pub struct NewMessage {
+    /// Chosen by the client so its own copy and the published event share it.
+    /// An id that is already stored is refused with 409 Conflict.
+    pub id: Option<i32>,
+    pub room: String,
+    pub body: String,
+}
+
+/// `PUT /read-receipts` body, for the session's user
+#[derive(Serialize, Deserialize)]
+pub struct ReadUpdate {
+    pub room: String,
+    pub message_id: i32,
+}
+
+/// `PUT /keys` body, the session's user's public keys in base64. Keys left out stay as they are.
+#[derive(Default, Serialize, Deserialize)]
+pub struct KeyUpdate {
+    #[serde(default)]
+    pub public_key: Option<String>,
+    #[serde(default)]
+    pub signing_key: Option<String>,
+}
+
+// Error responses of the service, see `CustomError::error_response`
+#[derive(Deserialize)]
+struct ErrorBody {
+    message: String,
+}
+
+/// Session with the HTTP service, which stores messages and read receipts as its user
+#[derive(Clone)]
+pub struct ApiClient {
+    url: String,
+    token: Zeroizing<String>,
+    email: String,
+}
+
+impl ApiClient {
+    /// Starts a session, failing with status 401 for a wrong user or password
+    pub fn log_in(
+        url: &str,
+        user: &str,
+        password: &str,
+        remember: bool,
+    ) -> Result<ApiClient, CustomError> {
+        let request = LoginRequest {
+            user: Cow::Borrowed(user),
+            password: Cow::Borrowed(password),
+            remember,
+        };
+        let response: LoginResponse =
+            receive(ureq::post(&format!("{}/sessions", url)).send_json(&request))?;
+        Ok(ApiClient {
+            url: url.to_string(),
+            token: Zeroizing::new(response.token),
+            email: response.email,
+        })
+    }
+
+    /// Picks up a remembered session, already checked against the database
+    pub fn resume(url: &str, token: &str, email: &str) -> ApiClient {
+        ApiClient {
+            url: url.to_string(),
+            token: Zeroizing::new(token.to_string()),
+            email: email.to_string(),
+        }
+    }
+
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// Email of the session's user
+    pub fn email(&self) -> &str {
+        &self.email
+    }
+
+    /// Revokes the session's token
+    pub fn log_out(&self) -> Result<(), CustomError> {
+        let request = self.authorized(ureq::delete(&format!("{}/sessions/current", self.url)));
+        receive_empty(request.call())
+    }
+
+    /// Stores a message, returning it as stored with the session's user as author
+    pub fn send_message(&self, message: &NewMessage) -> Result<Message, CustomError> {
+        let request = self.authorized(ureq::post(&format!("{}/messages", self.url)));
+        receive(request.send_json(message))
+    }
+
+    pub fn mark_read(&self, update: &ReadUpdate) -> Result<(), CustomError> {
+        let request = self.authorized(ureq::put(&format!("{}/read-receipts", self.url)));
+        receive_empty(request.send_json(update))
+    }
+
+    /// Publishes the session's user's keys, replacing any earlier ones
+    pub fn publish_keys(&self, keys: &KeyUpdate) -> Result<(), CustomError> {
+        let request = self.authorized(ureq::put(&format!("{}/keys", self.url)));
+        receive_empty(request.send_json(keys))
+    }
+
+    fn authorized(&self, request: ureq::Request) -> ureq::Request {
+        request.set("Authorization", &format!("Bearer {}", self.token.as_str()))
+    }
+}
+
+// Status codes and messages of the service come back as they are,
+// not reaching it at all is a 503 like a proxy would answer
+fn receive<T: DeserializeOwned>(
+    result: Result<ureq::Response, ureq::Error>,
+) -> Result<T, CustomError> {
+    result
+        .map_err(service_error)?
+        .into_json()
+        .map_err(|err| CustomError::new(502, format!("Unexpected answer from the server: {}", err)))
+}
+
+fn receive_empty(result: Result<ureq::Response, ureq::Error>) -> Result<(), CustomError> {
+    result.map(|_| ()).map_err(service_error)
+}
+
+fn service_error(error: ureq::Error) -> CustomError {
+    match error {
+        ureq::Error::Status(status, response) => {
+            let message = response.into_json::<ErrorBody>().map_or_else(
+                |_| format!("The server answered {}", status),
+                |body| body.message,
+            );
+            CustomError::new(status, message)
+        }
+        ureq::Error::Transport(err) => {
+            CustomError::new(503, format!("Could not reach the server: {}", err))
+        }
+    }
+}
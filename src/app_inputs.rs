@@ -1,142 +1,1326 @@
-use crate::events::{consumer, producer};
-use crate::model::models::{Message, WigglesUser};
+use crate::api::{ApiClient, KeyUpdate, NewMessage, ReadUpdate};
+use crate::audio_handlers::{AudioPlayer, Sound};
+use crate::credentials::{CredentialError, CredentialStore, SigningKeys};
+use crate::e2e::{self, E2eError, Envelope, Identity, KeyStatus, SigningIdentity};
+use crate::events::wiggles_event::{
+    PresenceEvent, PresenceStatus, ReadEvent, ReceivedEvent, TypingEvent, WigglesEvent,
+};
+use crate::events::{producer, utils};
+use crate::keymap::{Action, Keymap};
+use crate::mentions::{self, MentionCompletion};
+use crate::model::models::{Message, ReadReceipt, Session, WigglesUser};
+use crate::notifications::Notifier;
+use crate::presence::{self, PresenceMap, TypingIndicators};
+use crate::profiles::{ProfileState, Profiles};
+use crate::rooms;
+use crate::search::{self, Search};
+use crate::theme::Theme;
 use crate::ui_render_handler;
-use crossbeam_channel::TryRecvError::{self};
-use crossbeam_channel::{unbounded, Receiver, Sender};
-use crossterm::event::{self, Event, KeyCode};
+use chrono::Utc;
+use crossbeam_channel::Receiver;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use log::warn;
 use rand::Rng;
-use std::{error::Error, thread};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use std::{error::Error, mem, thread};
 use tui::{backend::Backend, Terminal};
+use zeroize::Zeroizing;
+
 pub enum InputMode {
     Normal,
     Editing,
     Login,
+    Search,
 }
 pub enum LoginInput {
     UserName,
     Password,
+    /// Passphrase for a new credential store, asked for on the first direct message
+    NewPassphrase,
+    RepeatPassphrase,
+}
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Pane {
+    Rooms,
+    Messages,
+    Members,
 }
 
 /// App holds the state of the application
 pub struct App {
     /// Current value of the input box
     pub input: String,
+    /// Password being typed on the login screen, kept out of `input` so it can be wiped
+    pub password: Zeroizing<String>,
     /// Current input mode
     pub input_mode: InputMode,
     /// History of recorded messages
     pub messages: Vec<Message>,
     pub login_input_mode: LoginInput,
+    /// Name of the logged in user
+    pub current_user: String,
+    /// Styles used to render every widget
+    pub theme: Theme,
+    /// Key bindings for every input mode
+    pub keymap: Keymap,
+    /// Names of registered users, used to complete @mentions
+    pub user_names: Vec<String>,
+    /// In progress @mention completion, reset by any key but Tab
+    pub mention_completion: Option<MentionCompletion>,
+    /// Rooms and direct messages listed in the sidebar
+    pub rooms: Vec<String>,
+    /// Room new messages are sent to and the message pane shows
+    pub current_room: String,
+    /// Id of the last message the logged in user has seen in each room
+    pub last_read: HashMap<String, i32>,
+    /// Last message other users have seen, by room and then user
+    pub seen_by: HashMap<String, HashMap<String, i32>>,
+    /// First message that was unread when the current room was opened
+    pub first_unread: Option<i32>,
+    /// Pane that navigation keys act on
+    pub focus: Pane,
+    /// Whether the member list pane is open
+    pub show_members: bool,
+    /// Online status of other users, from their heartbeats
+    pub presence: PresenceMap,
+    /// Other users currently typing
+    pub typing: TypingIndicators,
+    /// Bell, terminal and sound notifications for incoming messages
+    pub notifier: Notifier,
+    /// Audio thread playing notification and login sounds
+    pub audio: AudioPlayer,
+    /// Search results shown in place of the message pane while searching
+    pub search: Option<Search>,
+    /// Message opened from the search results, highlighted in the message pane
+    pub jump_to: Option<i32>,
+    /// Deployments to switch between with `/profile <name>`
+    pub profiles: Profiles,
+    /// Unlocked credential store, holding direct message keys and remembered sessions
+    pub credentials: Option<CredentialStore>,
+    /// Where the credential store is created when the first direct message needs it
+    pub credentials_path: Option<PathBuf>,
+    /// First entry of the passphrase for a new credential store, until it is repeated
+    pub new_passphrase: Zeroizing<String>,
+    /// Whether logins are remembered with a session token per profile
+    pub remember_me: bool,
+    /// Why the last login attempt failed
+    pub login_error: Option<String>,
+    /// Logged in user's direct message keypair
+    pub identity: Option<Identity>,
+    /// Signing keys by profile and user, which don't need the credential store's passphrase
+    pub signing_keys: Option<SigningKeys>,
+    /// Logged in user's key for signing the events they publish
+    pub signing: Option<SigningIdentity>,
+    /// Users' public keys by name, to encrypt direct messages to them
+    pub public_keys: HashMap<String, String>,
+    /// Whether a direct message pane lists both key fingerprints
+    pub show_keys: bool,
+    /// Why the last message or command didn't go through
+    pub notice: Option<String>,
+    /// Ids of messages from the transport that weren't signed by their author
+    pub unverified: HashSet<i32>,
+    /// Session with the HTTP service, which stores messages as the logged in user
+    pub api: Option<ApiClient>,
 }
 
 impl Default for App {
     fn default() -> App {
         App {
             input: String::new(),
+            password: Zeroizing::default(),
             input_mode: InputMode::Normal,
             messages: Vec::new(),
             login_input_mode: LoginInput::UserName,
+            current_user: String::new(),
+            theme: Theme::default(),
+            keymap: Keymap::default(),
+            user_names: Vec::new(),
+            mention_completion: None,
+            rooms: vec![rooms::default_room()],
+            current_room: rooms::default_room(),
+            last_read: HashMap::new(),
+            seen_by: HashMap::new(),
+            first_unread: None,
+            focus: Pane::Messages,
+            show_members: false,
+            presence: PresenceMap::default(),
+            typing: TypingIndicators::default(),
+            notifier: Notifier::default(),
+            audio: AudioPlayer::default(),
+            search: None,
+            jump_to: None,
+            profiles: Profiles::default(),
+            credentials: None,
+            credentials_path: None,
+            new_passphrase: Zeroizing::default(),
+            remember_me: false,
+            login_error: None,
+            identity: None,
+            signing_keys: None,
+            signing: None,
+            public_keys: HashMap::new(),
+            show_keys: false,
+            notice: None,
+            unverified: HashSet::new(),
+            api: None,
         }
     }
 }
+
+impl App {
+    /// Exchanges the shown profile's conversation with a stored one, login included
+    pub fn swap_state(&mut self, state: &mut ProfileState, user: &mut WigglesUser) {
+        mem::swap(&mut self.input_mode, &mut state.input_mode);
+        mem::swap(&mut self.login_input_mode, &mut state.login_input_mode);
+        mem::swap(user, &mut state.user);
+        mem::swap(&mut self.current_user, &mut state.current_user);
+        mem::swap(&mut self.messages, &mut state.messages);
+        mem::swap(&mut self.user_names, &mut state.user_names);
+        mem::swap(&mut self.rooms, &mut state.rooms);
+        mem::swap(&mut self.current_room, &mut state.current_room);
+        mem::swap(&mut self.last_read, &mut state.last_read);
+        mem::swap(&mut self.seen_by, &mut state.seen_by);
+        mem::swap(&mut self.first_unread, &mut state.first_unread);
+        mem::swap(&mut self.presence, &mut state.presence);
+        mem::swap(&mut self.typing, &mut state.typing);
+        mem::swap(&mut self.search, &mut state.search);
+        mem::swap(&mut self.jump_to, &mut state.jump_to);
+        mem::swap(&mut self.identity, &mut state.identity);
+        mem::swap(&mut self.signing, &mut state.signing);
+        mem::swap(&mut self.public_keys, &mut state.public_keys);
+        mem::swap(&mut self.unverified, &mut state.unverified);
+        mem::swap(&mut self.api, &mut state.api);
+        self.mention_completion = None;
+        self.notice = None;
+    }
+
+    /// Rooms the logged in user can see, in sidebar order
+    pub fn visible_rooms(&self) -> Vec<&String> {
+        self.rooms
+            .iter()
+            .filter(|room| rooms::is_visible(room, &self.current_user))
+            .collect()
+    }
+
+    /// Messages in the current room
+    pub fn room_messages(&self) -> impl Iterator<Item = &Message> {
+        self.messages
+            .iter()
+            .filter(move |m| m.room == self.current_room)
+    }
+
+    pub fn add_room(&mut self, room: &str) {
+        if !self.rooms.iter().any(|known| known == room) {
+            self.rooms.push(room.to_string());
+        }
+    }
+
+    /// Opens a room, remembering where its unread messages start for the divider
+    pub fn switch_room(&mut self, room: &str) {
+        self.add_room(room);
+        self.current_room = room.to_string();
+        self.jump_to = None;
+        let first_unread = self.unread_messages(room).next().map(|m| m.id);
+        self.first_unread = first_unread;
+    }
+
+    /// Adds a message from the transport, remembering when its author couldn't be verified.
+    /// Our own messages come back from the transport too, so known ids are skipped.
+    pub fn receive_message(&mut self, mut message: Message, verified: bool) {
+        if self.messages.iter().any(|known| known.id == message.id) {
+            return;
+        }
+        if !verified {
+            self.unverified.insert(message.id);
+        }
+        if !self.open_message(&mut message) {
+            // sealed with a key this client hasn't seen, the sender may have logged in since
+            if let Some(peer) = self.sealed_peer(&message).map(str::to_string) {
+                self.refresh_public_key(&peer);
+            }
+            if !self.open_message(&mut message) {
+                message.body = e2e::UNREADABLE.to_string();
+            }
+        }
+        self.add_room(&message.room);
+        self.typing.stopped(&message.room, &message.name);
+        self.notifier
+            .notify(&message, &self.current_user, &self.audio);
+        self.messages.push(message);
+    }
+
+    /// Other participant of a direct message to the logged in user that is still encrypted
+    fn sealed_peer<'a>(&self, message: &'a Message) -> Option<&'a str> {
+        rooms::dm_peer(&message.room, &self.current_user)
+            .filter(|_| e2e::is_encrypted(&message.body))
+    }
+
+    /// Decrypts a direct message body in place, returning false when it can't be read.
+    /// Bodies of other rooms, or before logging in, are left as they are.
+    fn open_message(&self, message: &mut Message) -> bool {
+        let peer = match self.sealed_peer(message) {
+            Some(peer) => peer,
+            None => return true,
+        };
+        let opened = match &self.identity {
+            Some(identity) => identity.decrypt(
+                peer,
+                self.public_keys.get(peer).map(String::as_str),
+                envelope(message),
+                &message.body,
+            ),
+            None => Err(E2eError::NoIdentity),
+        };
+        match opened {
+            Ok(body) => {
+                message.body = body;
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Decrypts loaded direct messages, with a placeholder for the unreadable ones
+    fn open_messages(&self, messages: &mut [Message]) {
+        for message in messages {
+            if !self.open_message(message) {
+                message.body = e2e::UNREADABLE.to_string();
+            }
+        }
+    }
+
+    // Looks up a user's current public key, they may have logged in since the list was loaded
+    fn refresh_public_key(&mut self, name: &str) {
+        match WigglesUser::find_by_name(name) {
+            Ok(Some(WigglesUser {
+                public_key: Some(key),
+                ..
+            })) => {
+                self.public_keys.insert(name.to_string(), key);
+            }
+            Ok(_) => {}
+            Err(err) => warn!("Failed to look up the key of {}: {}", name, err),
+        }
+    }
+
+    /// Body to publish and store, encrypted for the other participant in a direct message room
+    fn seal_body(&mut self, message: &Message) -> Result<String, E2eError> {
+        let peer = match rooms::dm_peer(&self.current_room, &self.current_user) {
+            Some(peer) => peer.to_string(),
+            None => return Ok(message.body.to_string()),
+        };
+        if !self.public_keys.contains_key(&peer) {
+            self.refresh_public_key(&peer);
+        }
+        // nothing is sealed to a key that replaced the verified one until it is verified again
+        if self.key_status(&peer) == KeyStatus::Changed {
+            return Err(E2eError::KeyChanged(peer));
+        }
+        let identity = self.identity.as_ref().ok_or(E2eError::NoIdentity)?;
+        identity.encrypt(
+            &peer,
+            self.public_keys.get(&peer).map(String::as_str),
+            envelope(message),
+            &message.body,
+        )
+    }
+
+    /// Other participant of the open direct message room
+    pub fn current_peer(&self) -> Option<&str> {
+        rooms::dm_peer(&self.current_room, &self.current_user)
+    }
+
+    /// Whether the other participant's key matches the fingerprint pinned with `/verify`
+    pub fn key_status(&self, peer: &str) -> KeyStatus {
+        let fingerprint = match self
+            .public_keys
+            .get(peer)
+            .and_then(|key| e2e::fingerprint(key))
+        {
+            Some(fingerprint) => fingerprint,
+            None => return KeyStatus::Missing,
+        };
+        let profile = self.profiles.active_name();
+        match self
+            .credentials
+            .as_ref()
+            .and_then(|store| store.verified_fingerprint(profile, peer))
+        {
+            Some(verified) if verified == fingerprint => KeyStatus::Verified,
+            Some(_) => KeyStatus::Changed,
+            None => KeyStatus::Unverified,
+        }
+    }
+
+    /// Asks for a passphrase to create the credential store the direct message key is kept in.
+    /// Users who never send direct messages aren't asked for one.
+    fn start_store_setup(&mut self) {
+        self.input_mode = InputMode::Login;
+        self.login_input_mode = LoginInput::NewPassphrase;
+        self.password = Zeroizing::new(String::with_capacity(PASSWORD_CAPACITY));
+        self.notice = Some(
+            "Direct messages are end to end encrypted, choose a passphrase for the store that keeps your key"
+                .to_string(),
+        );
+    }
+
+    // Leaves the passphrase prompt without creating the store
+    fn cancel_store_setup(&mut self) {
+        self.input_mode = InputMode::Editing;
+        self.login_input_mode = LoginInput::UserName;
+        self.password = Zeroizing::default();
+        self.new_passphrase = Zeroizing::default();
+        self.notice = None;
+    }
+
+    // Pins the other participant's current fingerprint once it has been compared out of band
+    fn verify_peer(&mut self) -> bool {
+        let peer = match self.current_peer() {
+            Some(peer) => peer.to_string(),
+            None => return false,
+        };
+        self.refresh_public_key(&peer);
+        let fingerprint = match self
+            .public_keys
+            .get(&peer)
+            .and_then(|key| e2e::fingerprint(key))
+        {
+            Some(fingerprint) => fingerprint,
+            None => {
+                self.notice = Some(E2eError::NoKey(peer).to_string());
+                return true;
+            }
+        };
+        let profile = self.profiles.active_name().to_string();
+        if let Some(store) = &mut self.credentials {
+            store.set_verified(&profile, &peer, &fingerprint);
+            if let Err(err) = store.save() {
+                warn!("Failed to save the verified key of {}: {}", peer, err);
+            }
+        }
+        self.show_keys = true;
+        true
+    }
+
+    /// Messages from other users after the logged in user's last read one in a room.
+    /// Without a receipt, or once it has scrolled out of history, everything is unread.
+    fn unread_messages<'a>(&'a self, room: &'a str) -> impl Iterator<Item = &'a Message> {
+        let in_room: Vec<&Message> = self.messages.iter().filter(|m| m.room == room).collect();
+        let start = self
+            .last_read
+            .get(room)
+            .and_then(|id| in_room.iter().position(|m| m.id == *id))
+            .map_or(0, |i| i + 1);
+        in_room
+            .into_iter()
+            .skip(start)
+            .filter(move |m| m.name != self.current_user)
+    }
+
+    pub fn unread_count(&self, room: &str) -> usize {
+        match self.current_user.is_empty() {
+            true => 0,
+            false => self.unread_messages(room).count(),
+        }
+    }
+
+    /// Loads read receipts once the user has logged in
+    pub fn load_read_receipts(&mut self, receipts: Vec<ReadReceipt>) {
+        for receipt in receipts {
+            match receipt.user_name == self.current_user {
+                true => {
+                    self.last_read
+                        .insert(receipt.room, receipt.last_read_message_id);
+                }
+                false => {
+                    self.seen_by
+                        .entry(receipt.room)
+                        .or_default()
+                        .insert(receipt.user_name, receipt.last_read_message_id);
+                }
+            }
+        }
+        let room = self.current_room.to_string();
+        self.switch_room(&room);
+    }
+
+    /// Records another user's read event
+    pub fn receive_read(&mut self, read: ReadEvent) {
+        if read.name != self.current_user {
+            self.seen_by
+                .entry(read.room)
+                .or_default()
+                .insert(read.name, read.message_id);
+        }
+    }
+
+    /// Marks the open room read up to its newest message,
+    /// returning the receipt to store and publish when it moved
+    fn mark_read(&mut self) -> Option<ReadReceipt> {
+        let newest = self.room_messages().last()?.id;
+        let on_screen =
+            !self.current_user.is_empty() && !matches!(self.input_mode, InputMode::Login);
+        if !on_screen || self.last_read.get(&self.current_room) == Some(&newest) {
+            return None;
+        }
+        self.last_read.insert(self.current_room.to_string(), newest);
+        Some(ReadReceipt {
+            user_name: self.current_user.to_string(),
+            room: self.current_room.to_string(),
+            last_read_message_id: newest,
+        })
+    }
+
+    /// Other users who have read the open room up to its newest message
+    pub fn seen_newest(&self) -> Vec<&str> {
+        let newest = match self.room_messages().last() {
+            Some(newest) => newest,
+            None => return Vec::new(),
+        };
+        let mut names: Vec<&str> = self
+            .seen_by
+            .get(&self.current_room)
+            .into_iter()
+            .flatten()
+            .filter(|(name, id)| **id == newest.id && **name != newest.name)
+            .map(|(name, _)| name.as_str())
+            .collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Moves the sidebar selection up or down, switching to that room
+    pub fn select_room(&mut self, forward: bool) {
+        let visible = self.visible_rooms();
+        if visible.is_empty() {
+            return;
+        }
+        let current = visible
+            .iter()
+            .position(|room| **room == self.current_room)
+            .unwrap_or(0);
+        let next = match forward {
+            true => (current + 1) % visible.len(),
+            false => (current + visible.len() - 1) % visible.len(),
+        };
+        let room = visible[next].to_string();
+        self.switch_room(&room);
+    }
+
+    /// Panes focus cycles through, in screen order
+    pub fn panes(&self) -> Vec<Pane> {
+        match self.show_members {
+            true => vec![Pane::Rooms, Pane::Messages, Pane::Members],
+            false => vec![Pane::Rooms, Pane::Messages],
+        }
+    }
+
+    pub fn cycle_focus(&mut self, forward: bool) {
+        let panes = self.panes();
+        let current = panes
+            .iter()
+            .position(|pane| *pane == self.focus)
+            .unwrap_or(0);
+        let next = match forward {
+            true => (current + 1) % panes.len(),
+            false => (current + panes.len() - 1) % panes.len(),
+        };
+        self.focus = panes[next];
+    }
+
+    pub fn toggle_members(&mut self) {
+        self.show_members = !self.show_members;
+        if !self.show_members && self.focus == Pane::Members {
+            self.focus = Pane::Messages;
+        }
+    }
+
+    /// Starts a search, keeping the last query in the input
+    pub fn start_search(&mut self) {
+        let search = self.search.take().unwrap_or_default();
+        self.input = search.query.to_string();
+        self.search = Some(search);
+        self.input_mode = InputMode::Search;
+        self.focus = Pane::Messages;
+    }
+
+    pub fn close_search(&mut self) {
+        self.search = None;
+        self.input.clear();
+        self.input_mode = InputMode::Normal;
+    }
+
+    /// Runs the query in the input, or opens the selected result when it's already been run
+    pub fn submit_search(&mut self) {
+        let query = self.input.trim().to_string();
+        let search = match &mut self.search {
+            Some(search) => search,
+            None => return,
+        };
+        if query.is_empty() {
+            return;
+        }
+        if query != search.query {
+            let results = Message::search(&query, None, &self.current_user, search::RESULT_LIMIT)
+                .unwrap_or_else(|err| {
+                    warn!("Search for {:?} failed: {}", query, err);
+                    Vec::new()
+                });
+            // direct messages between other users never show up
+            let mut results: Vec<Message> = results
+                .into_iter()
+                .filter(|m| rooms::is_visible(&m.room, &self.current_user))
+                .collect();
+            self.open_messages(&mut results);
+            self.search = Some(Search {
+                query,
+                results,
+                ..Search::default()
+            });
+            return;
+        }
+        let result = match search.selected_result() {
+            Some(result) => result.clone(),
+            None => return,
+        };
+        if self.messages.iter().any(|m| m.id == result.id) {
+            self.close_search();
+            self.switch_room(&result.room);
+            self.jump_to = Some(result.id);
+            return;
+        }
+        match Message::context(result.id, &result.room, search::CONTEXT_RADIUS) {
+            Ok(mut context) => {
+                self.open_messages(&mut context);
+                if let Some(search) = &mut self.search {
+                    search.context = Some(context);
+                }
+            }
+            Err(err) => warn!("Failed to load messages around {}: {}", result.id, err),
+        }
+    }
+
+    /// Leaves the opened context first, then the search itself
+    pub fn cancel_search(&mut self) {
+        match self
+            .search
+            .as_mut()
+            .and_then(|search| search.context.take())
+        {
+            Some(_) => {}
+            None => self.close_search(),
+        }
+    }
+
+    // `/join <room>` and `/dm <user>` switch conversations instead of sending a message,
+    // `/mute` and `/unmute` toggle notifications for the current one, `/volume <0-100>` sets the volume.
+    // In a direct message `/keys` shows both key fingerprints and `/verify` pins the other one.
+    // `/profile <name>` needs the logged in user too, so `switch_profile` handles it.
+    fn run_command(&mut self, body: &str) -> bool {
+        let (command, argument) = match body.split_once(' ') {
+            Some((command, argument)) => (command, argument.trim()),
+            None => (body, ""),
+        };
+        match command {
+            "/join" if rooms::is_valid_name(argument) => self.switch_room(argument),
+            "/dm" if !argument.is_empty() && !self.current_user.is_empty() => {
+                let room = rooms::dm_room(&self.current_user, argument.trim_start_matches('@'));
+                self.switch_room(&room);
+                if self.credentials.is_none() {
+                    self.start_store_setup();
+                }
+            }
+            "/keys" if self.current_peer().is_some() => self.show_keys = !self.show_keys,
+            "/verify" => return self.verify_peer(),
+            "/mute" => self.notifier.set_muted(&self.current_room, true),
+            "/unmute" => self.notifier.set_muted(&self.current_room, false),
+            "/volume" => match argument.parse::<u8>() {
+                Ok(percent) if percent <= 100 => self.audio.set_volume(f32::from(percent) / 100.0),
+                _ => return false,
+            },
+            _ => return false,
+        }
+        true
+    }
+}
 pub fn run_app<B: Backend>(
     terminal: &mut Terminal<B>,
     mut app: App,
     mut user: WigglesUser,
 ) -> Result<(), Box<dyn Error>> {
-    app.messages = ui_render_handler::remove_old_messages(Message::get().unwrap());
-    let (sender, receiver): (Sender<String>, Receiver<String>) = unbounded();
-    thread::Builder::new()
-        .name("kafka consumer thread".to_string())
-        .spawn(move || consumer::start_consuming(sender.clone()).unwrap())
-        .unwrap();
+    let active = app.profiles.active();
+    app.profiles.connect(active)?;
+    app.profiles.activate(active);
+    app.swap_state(&mut ProfileState::load(), &mut user);
+    resume_session(&mut app, &mut user);
+
+    let mut last_input = Instant::now();
+    let mut last_heartbeat: Option<Instant> = None;
+    let mut last_typing: Option<Instant> = None;
 
     loop {
         //event consumer
         app.messages = ui_render_handler::remove_old_messages(app.messages);
+        sync_read_receipt(&mut app);
         terminal.draw(|f| ui_render_handler::ui(f, &app))?;
 
+        let heartbeat_due =
+            last_heartbeat.is_none_or(|sent| sent.elapsed() >= presence::HEARTBEAT_INTERVAL);
+        if !app.current_user.is_empty() && heartbeat_due {
+            let status = match last_input.elapsed() >= presence::AWAY_AFTER {
+                true => PresenceStatus::Away,
+                false => PresenceStatus::Online,
+            };
+            publish(
+                &app,
+                WigglesEvent::Presence(PresenceEvent::new(&app.current_user, status)),
+            );
+            last_heartbeat = Some(Instant::now());
+        }
+
+        // poll instead of blocking so incoming events and presence timeouts render without a key press
+        if !event::poll(TICK_RATE)? {
+            receive_events(&mut app);
+            continue;
+        }
         if let Event::Key(key) = event::read()? {
+            last_input = Instant::now();
+            let action = match app.input_mode {
+                InputMode::Normal => app.keymap.normal_action(key),
+                InputMode::Login | InputMode::Editing | InputMode::Search => {
+                    app.keymap.insert_action(key)
+                }
+            };
+            if action != Some(Action::CompleteMention) {
+                app.mention_completion = None;
+            }
             match app.input_mode {
-                InputMode::Normal => match key.code {
-                    KeyCode::Char('e') => {
+                InputMode::Normal => match action {
+                    Some(Action::StartEditing) => {
                         app.input_mode = InputMode::Editing;
                     }
-                    KeyCode::Char('l') => {
+                    Some(Action::Login) => {
                         app.input_mode = InputMode::Login;
                     }
-                    KeyCode::Char('q') => {
+                    Some(Action::Quit) => {
+                        if !app.current_user.is_empty() {
+                            let offline =
+                                PresenceEvent::new(&app.current_user, PresenceStatus::Offline);
+                            // wait for it, the process exits right after
+                            publish(&app, WigglesEvent::Presence(offline)).join().ok();
+                        }
+                        // a remembered session outlives the run, any other ends with it
+                        if let (Some(api), false) = (&app.api, app.remember_me) {
+                            api.log_out().ok();
+                        }
                         return Ok(());
                     }
+                    Some(Action::FocusNext) => app.cycle_focus(true),
+                    Some(Action::FocusPrevious) => app.cycle_focus(false),
+                    Some(Action::SelectNext) if app.focus == Pane::Rooms => app.select_room(true),
+                    Some(Action::SelectPrevious) if app.focus == Pane::Rooms => {
+                        app.select_room(false)
+                    }
+                    Some(Action::ToggleMembers) => app.toggle_members(),
+                    Some(Action::Search) if !app.current_user.is_empty() => app.start_search(),
                     _ => {}
                 },
-                InputMode::Login => match key.code {
-                    KeyCode::Enter => match app.login_input_mode {
+                InputMode::Search => match action {
+                    Some(Action::Submit) => app.submit_search(),
+                    Some(Action::Cancel) => app.cancel_search(),
+                    Some(Action::NextResult) => {
+                        if let Some(search) = &mut app.search {
+                            search.select(true);
+                        }
+                    }
+                    Some(Action::PreviousResult) => {
+                        if let Some(search) = &mut app.search {
+                            search.select(false);
+                        }
+                    }
+                    _ => edit_input(&mut app.input, action, key),
+                },
+                InputMode::Login => match action {
+                    Some(Action::Submit) => match app.login_input_mode {
                         LoginInput::UserName => {
                             user.name = app.input.drain(..).collect();
+                            app.login_error = None;
                             app.login_input_mode = LoginInput::Password;
+                            app.password = Zeroizing::new(String::with_capacity(PASSWORD_CAPACITY));
                         }
                         LoginInput::Password => {
-                            user.password = app.input.drain(..).collect();
-                            app.input_mode = InputMode::Editing;
+                            // wiped once checked
+                            let password = mem::take(&mut app.password);
+                            app.login_input_mode = LoginInput::UserName;
+                            let session = ApiClient::log_in(
+                                app.profiles.api_url(),
+                                &user.name,
+                                &password,
+                                app.remember_me,
+                            );
+                            match session.and_then(|api| {
+                                let stored = WigglesUser::find_by_email(api.email())?;
+                                Ok((api, stored))
+                            }) {
+                                Ok((api, Some(stored))) => {
+                                    user = stored;
+                                    app.api = Some(api);
+                                    log_in(&mut app, &mut user);
+                                    remember_session(&mut app);
+                                }
+                                Ok((_, None)) => {
+                                    app.login_error =
+                                        Some("Wrong user name or password.".to_string())
+                                }
+                                Err(err) if err.error_status_code == 401 => {
+                                    app.login_error =
+                                        Some("Wrong user name or password.".to_string())
+                                }
+                                Err(err) => {
+                                    warn!("Failed to log in {}: {}", user.name, err);
+                                    app.login_error = Some(format!("Could not log in: {}", err));
+                                }
+                            }
+                        }
+                        LoginInput::NewPassphrase => {
+                            app.new_passphrase = mem::take(&mut app.password);
+                            app.login_input_mode = LoginInput::RepeatPassphrase;
+                            app.password = Zeroizing::new(String::with_capacity(PASSWORD_CAPACITY));
+                        }
+                        LoginInput::RepeatPassphrase => {
+                            let repeated = mem::take(&mut app.password);
+                            let passphrase = mem::take(&mut app.new_passphrase);
+                            match passphrase.is_empty() || passphrase != repeated {
+                                true => {
+                                    app.start_store_setup();
+                                    app.notice = Some(
+                                        "The passphrases were empty or didn't match, try again"
+                                            .to_string(),
+                                    );
+                                }
+                                false => create_credential_store(&mut app, &mut user, &passphrase),
+                            }
                         }
                     },
-                    KeyCode::Backspace => {
-                        app.input.pop();
-                    }
-                    KeyCode::Esc => {
-                        app.input_mode = InputMode::Normal;
-                    }
-                    KeyCode::Char(c) => {
-                        app.input.push(c);
-                    }
-                    _ => {}
+                    Some(Action::Cancel) => match app.login_input_mode {
+                        LoginInput::UserName | LoginInput::Password => {
+                            app.input_mode = InputMode::Normal;
+                            app.password = Zeroizing::default();
+                        }
+                        LoginInput::NewPassphrase | LoginInput::RepeatPassphrase => {
+                            app.cancel_store_setup()
+                        }
+                    },
+                    _ => match app.login_input_mode {
+                        LoginInput::UserName => edit_input(&mut app.input, action, key),
+                        _ => edit_password(&mut app.password, action, key),
+                    },
                 },
 
-                InputMode::Editing => match key.code {
-                    KeyCode::Enter => {
+                InputMode::Editing => match action {
+                    Some(Action::CompleteMention) => {
+                        mentions::complete(
+                            &mut app.input,
+                            &app.user_names,
+                            &mut app.mention_completion,
+                        );
+                    }
+                    Some(Action::Submit) => {
                         //Where Message struct is instantiated
-                        let body = app.input.drain(..).collect();
+                        let body: String = app.input.drain(..).collect();
+                        app.notice = None;
+                        let is_command = body.starts_with('/')
+                            && match body.strip_prefix("/profile ") {
+                                Some(name) => switch_profile(&mut app, &mut user, name.trim()),
+                                None if body == "/newkey" => {
+                                    load_identity(&mut app, &mut user, true);
+                                    load_signing_key(&mut app, &mut user, true);
+                                    true
+                                }
+                                None => app.run_command(&body),
+                            };
 
                         let mut rng = rand::thread_rng();
                         let message = Message {
                             id: rng.gen(),
                             name: user.name.to_string(),
-                            body: body,
+                            body,
                             published: true,
+                            room: app.current_room.to_string(),
+                            created_at: Utc::now(),
+                            author_email: user.email.to_string(),
                         };
 
-                        if message.body != "" {
-                            let new_body = serde_json::to_string(&message).unwrap();
-
-                            app.messages.push(message.clone());
-                            thread::spawn(move || {
-                                message.insert().unwrap();
-                            });
-                            thread::spawn(move || {
-                                producer::produce_event(new_body).unwrap();
-                            });
-                            thread::spawn(move || {});
+                        if !is_command && !message.body.is_empty() {
+                            // direct messages leave this client encrypted, only the local copy is plaintext
+                            let sealed = match app.api.clone() {
+                                // other clients drop unsigned messages once a signing key is published
+                                Some(_) if app.signing.is_none() => Err(
+                                    "Your messages can't be signed here, so other clients would drop them. \
+                                     Type /newkey to sign with a new key"
+                                        .to_string(),
+                                ),
+                                Some(api) => app
+                                    .seal_body(&message)
+                                    .map(|body| {
+                                        let sealed = Message {
+                                            body,
+                                            ..message.clone()
+                                        };
+                                        (api, sealed)
+                                    })
+                                    .map_err(|err| err.to_string()),
+                                None => Err("Log in to send messages".to_string()),
+                            };
+                            match sealed {
+                                Ok((api, sealed)) => {
+                                    app.messages.push(message);
+                                    publish(&app, WigglesEvent::Message(sealed.clone()));
+                                    // the server stores it as the session's user
+                                    thread::spawn(move || {
+                                        let new_message = NewMessage {
+                                            id: Some(sealed.id),
+                                            room: sealed.room,
+                                            body: sealed.body,
+                                        };
+                                        if let Err(err) = api.send_message(&new_message) {
+                                            warn!("Failed to store message: {}", err);
+                                        }
+                                    });
+                                }
+                                Err(_)
+                                    if app.credentials.is_none()
+                                        && app.signing.is_some()
+                                        && app.current_peer().is_some() =>
+                                {
+                                    app.input = message.body;
+                                    app.start_store_setup();
+                                }
+                                Err(err) => {
+                                    app.notice = Some(err);
+                                    app.input = message.body;
+                                }
+                            }
                         }
                     }
-                    KeyCode::Char(c) => {
-                        app.input.push(c);
-                    }
-                    KeyCode::Backspace => {
-                        app.input.pop();
-                    }
-                    KeyCode::Esc => {
+                    Some(Action::Cancel) => {
                         app.input_mode = InputMode::Normal;
                     }
-                    _ => {}
+                    _ => {
+                        let before = app.input.len();
+                        edit_input(&mut app.input, action, key);
+                        if app.input.len() != before
+                            && presence::typing_due(last_typing)
+                            && !app.current_user.is_empty()
+                        {
+                            let typing = TypingEvent::new(&app.current_user, &app.current_room);
+                            publish(&app, WigglesEvent::Typing(typing));
+                            last_typing = Some(Instant::now());
+                        }
+                    }
                 },
             }
         }
-        let message_receiver: Result<String, TryRecvError> = receiver.try_recv();
-        match message_receiver {
-            Ok(message_receiver) => app
-                .messages
-                .push(serde_json::from_str(&message_receiver).unwrap()),
-            _ => (),
+        receive_events(&mut app);
+    }
+}
+
+const TICK_RATE: Duration = Duration::from_millis(250);
+
+// Applies every event the active profile's consumer has received so far.
+// Other profiles keep theirs queued until they are opened again.
+fn receive_events(app: &mut App) {
+    let receiver: Receiver<ReceivedEvent> = match app.profiles.receiver() {
+        Some(receiver) => receiver,
+        None => return,
+    };
+    // forged events were already dropped by the consumer
+    while let Ok(received) = receiver.try_recv() {
+        match received.event {
+            WigglesEvent::Message(message) => app.receive_message(message, received.verified),
+            WigglesEvent::Presence(presence) => app.presence.update(&presence),
+            WigglesEvent::Typing(typing) => app.typing.update(&typing),
+            WigglesEvent::Read(read) => app.receive_read(read),
+        }
+    }
+}
+
+// Stores and publishes a receipt once the open room has new messages on screen
+fn sync_read_receipt(app: &mut App) {
+    let receipt = match app.mark_read() {
+        Some(receipt) => receipt,
+        None => return,
+    };
+    publish(
+        app,
+        WigglesEvent::Read(ReadEvent {
+            name: receipt.user_name.to_string(),
+            room: receipt.room.to_string(),
+            message_id: receipt.last_read_message_id,
+        }),
+    );
+    let api = match &app.api {
+        Some(api) => api.clone(),
+        None => return,
+    };
+    thread::spawn(move || {
+        let update = ReadUpdate {
+            room: receipt.room,
+            message_id: receipt.last_read_message_id,
+        };
+        if let Err(err) = api.mark_read(&update) {
+            warn!("Failed to store read receipt: {}", err);
+        }
+    });
+}
+
+// Connects to another profile and shows its conversation, keeping the current one for later
+fn switch_profile(app: &mut App, user: &mut WigglesUser, name: &str) -> bool {
+    let index = match app.profiles.position(name) {
+        Some(index) => index,
+        None => return false,
+    };
+    let previous = app.profiles.active();
+    if index == previous {
+        return true;
+    }
+    if let Err(err) = app.profiles.connect(index) {
+        warn!("Failed to connect to profile {}: {}", name, err);
+        return true;
+    }
+    app.profiles.activate(index);
+    let stored = app.profiles.take_state(index);
+    let opened = stored.is_none();
+    let mut state = stored.unwrap_or_else(ProfileState::load);
+    app.swap_state(&mut state, user);
+    app.profiles.store_state(previous, state);
+    if opened {
+        resume_session(app, user);
+    }
+    true
+}
+
+// Shows the conversation as a user whose password or session checked out
+fn log_in(app: &mut App, user: &mut WigglesUser) {
+    app.current_user = user.name.to_string();
+    app.login_error = None;
+    app.input_mode = InputMode::Editing;
+    load_identity(app, user, false);
+    load_signing_key(app, user, false);
+    let mut messages = mem::take(&mut app.messages);
+    app.open_messages(&mut messages);
+    app.messages = messages;
+    app.load_read_receipts(ReadReceipt::get().unwrap_or_default());
+    app.audio.play(Sound::Login);
+}
+
+// Takes the user's direct message keys from the credential store, creating them on their first
+// login here, and publishes the public key when the stored one differs.
+// A key published from another machine is only replaced with `replace`, i.e. after /newkey
+fn load_identity(app: &mut App, user: &mut WigglesUser, replace: bool) {
+    let profile = app.profiles.active_name().to_string();
+    let store = match &mut app.credentials {
+        Some(store) => store,
+        None => return,
+    };
+    let stored = store.identity(&profile, &user.name).filter(|_| !replace);
+    let published_elsewhere = match (&stored, &user.public_key) {
+        (_, None) => false,
+        (Some(identity), Some(published)) => identity.public_base64() != *published,
+        (None, Some(_)) => true,
+    };
+    if published_elsewhere && !replace {
+        app.notice = Some(
+            "Your direct message key was published from another machine. Type /newkey to replace it, \
+             messages sealed to the old key won't open here and others will see your key changed"
+                .to_string(),
+        );
+        return;
+    }
+    let identity = match stored {
+        Some(identity) => identity,
+        None => {
+            let identity = Identity::generate();
+            store.set_identity(&profile, &user.name, &identity);
+            if let Err(err) = store.save() {
+                warn!("Failed to save the direct message key: {}", err);
+                return;
+            }
+            identity
+        }
+    };
+    let public_key = identity.public_base64();
+    if user.public_key.as_deref() != Some(public_key.as_str()) {
+        let keys = KeyUpdate {
+            public_key: Some(public_key.to_string()),
+            ..KeyUpdate::default()
         };
+        match app.api.as_ref().map(|api| api.publish_keys(&keys)) {
+            Some(Ok(())) => user.public_key = Some(public_key.to_string()),
+            Some(Err(err)) => warn!("Failed to publish the direct message key: {}", err),
+            None => warn!("Can't publish the direct message key without a session"),
+        }
+    }
+    app.public_keys.insert(user.name.to_string(), public_key);
+    app.identity = Some(identity);
+}
+
+// Takes the user's signing key from its file, creating and publishing one on their first login
+// here. It doesn't need the credential store, so every logged in user signs their events.
+// A key published from another machine is only replaced with `replace`, i.e. after /newkey
+fn load_signing_key(app: &mut App, user: &mut WigglesUser, replace: bool) {
+    app.signing = None;
+    let profile = app.profiles.active_name().to_string();
+    let keys = match &mut app.signing_keys {
+        Some(keys) => keys,
+        None => return,
+    };
+    let published = user.signing_key.as_deref();
+    let saved = keys.get(&profile, &user.name);
+    let saved_key = saved.as_ref().map(SigningIdentity::public_base64);
+    // earlier versions derived the signing key from the direct message key, it carries over
+    let carried_over = || {
+        let identity = app.credentials.as_ref()?.identity(&profile, &user.name)?;
+        Some(identity.signing_identity())
+            .filter(|signing| published == Some(&signing.public_base64()))
+    };
+    let stored = match replace {
+        true => None,
+        false => saved.or_else(carried_over),
+    };
+    let published_elsewhere = match (&stored, published) {
+        (_, None) => false,
+        (Some(signing), Some(published)) => signing.public_base64() != published,
+        (None, Some(_)) => true,
+    };
+    if published_elsewhere && !replace {
+        app.notice = Some(
+            "Your signing key was published from another machine. Type /newkey to replace it \
+             with one kept here"
+                .to_string(),
+        );
+        return;
+    }
+    let signing = stored.unwrap_or_else(SigningIdentity::generate);
+    if saved_key != Some(signing.public_base64()) {
+        keys.set(&profile, &user.name, &signing);
+        if let Err(err) = keys.save() {
+            warn!("Failed to save the signing key: {}", err);
+            return;
+        }
+    }
+    let signing_key = signing.public_base64();
+    if published != Some(signing_key.as_str()) {
+        let keys = KeyUpdate {
+            signing_key: Some(signing_key.to_string()),
+            ..KeyUpdate::default()
+        };
+        match app.api.as_ref().map(|api| api.publish_keys(&keys)) {
+            Some(Ok(())) => user.signing_key = Some(signing_key),
+            Some(Err(err)) => {
+                warn!("Failed to publish the signing key: {}", err);
+                return;
+            }
+            None => {
+                warn!("Can't publish the signing key without a session");
+                return;
+            }
+        }
+    }
+    app.signing = Some(signing);
+}
+
+// What a direct message body is sealed to besides the keys, its id, room and author
+fn envelope(message: &Message) -> Envelope<'_> {
+    Envelope {
+        id: message.id,
+        room: &message.room,
+        sender: &message.name,
+    }
+}
+
+// Creates the credential store once a passphrase was chosen, then the keys kept in it
+fn create_credential_store(app: &mut App, user: &mut WigglesUser, passphrase: &str) {
+    let created = match &app.credentials_path {
+        Some(path) => CredentialStore::create(path, passphrase)
+            .and_then(|store| store.save().map(|_| store))
+            .map_err(|err| err.to_string()),
+        None => Err(CredentialError::NoPath.to_string()),
+    };
+    app.cancel_store_setup();
+    match created {
+        Ok(store) => {
+            app.credentials = Some(store);
+            load_identity(app, user, false);
+        }
+        Err(err) => {
+            warn!("Failed to create the credential store: {}", err);
+            app.notice = Some(format!("Could not create the credential store: {}", err));
+        }
+    }
+}
+
+// Keeps the session token for the active profile when "remember me" is on,
+// revoking the one it replaces
+fn remember_session(app: &mut App) {
+    let (store, api) = match (&mut app.credentials, &app.api) {
+        (Some(store), Some(api)) if app.remember_me => (store, api),
+        _ => return,
+    };
+    let profile = app.profiles.active_name();
+    if let Some(old) = store.take_session(profile) {
+        ApiClient::resume(app.profiles.api_url(), &old, api.email())
+            .log_out()
+            .ok();
+    }
+    store.set_session(profile, api.token());
+    if let Err(err) = store.save() {
+        warn!("Failed to save the session: {}", err);
+    }
+}
+
+// Logs in with the active profile's remembered session, forgetting it once expired
+fn resume_session(app: &mut App, user: &mut WigglesUser) {
+    let profile = app.profiles.active_name().to_string();
+    let token = match app
+        .credentials
+        .as_ref()
+        .filter(|_| app.remember_me)
+        .and_then(|store| store.session(&profile))
+    {
+        Some(token) => Zeroizing::new(token.to_string()),
+        None => return,
+    };
+    match Session::user(&token) {
+        Ok(Some(stored)) => {
+            *user = stored;
+            app.api = Some(ApiClient::resume(
+                app.profiles.api_url(),
+                &token,
+                &user.email,
+            ));
+            log_in(app, user);
+        }
+        Ok(None) => {
+            if let Some(store) = &mut app.credentials {
+                store.take_session(&profile);
+                store.save().ok();
+            }
+        }
+        Err(err) => warn!("Failed to resume the session: {}", err),
+    }
+}
+
+// The producer runs its own runtime, so it can't be called from the UI thread directly.
+// The transport is picked here so a profile switch can't redirect the event.
+// Events are signed with the logged in user's key so other clients can tell they are theirs.
+fn publish(app: &App, event: WigglesEvent) -> thread::JoinHandle<()> {
+    let payload = match (&app.signing, &app.api) {
+        (Some(signing), Some(api)) => event.to_signed_payload(signing, api.email()),
+        _ => event.to_payload(),
+    };
+    let transport = utils::active();
+    thread::spawn(move || {
+        if let Err(err) = producer::produce_event(&transport, payload) {
+            warn!("Failed to publish event: {}", err);
+        }
+    })
+}
+
+// Room for a typical password, so typing one doesn't need a bigger buffer
+const PASSWORD_CAPACITY: usize = 64;
+
+// Edits the password being typed. Growing it in place would leave a copy in freed memory,
+// so a full buffer is moved to a bigger one by hand and the old one wiped.
+fn edit_password(password: &mut Zeroizing<String>, action: Option<Action>, key: KeyEvent) {
+    if password.capacity() - password.len() < char::MAX.len_utf8() {
+        let capacity = password.capacity() * 2 + PASSWORD_CAPACITY;
+        let mut grown = Zeroizing::new(String::with_capacity(capacity));
+        grown.push_str(password);
+        *password = grown;
+    }
+    edit_input(password, action, key);
+}
+
+// Applies text editing actions, typing the key's character when it isn't bound
+fn edit_input(input: &mut String, action: Option<Action>, key: KeyEvent) {
+    match action {
+        Some(Action::DeleteBackward) => {
+            input.pop();
+        }
+        Some(Action::DeleteWord) => {
+            let trimmed = input.trim_end().len();
+            input.truncate(trimmed);
+            let word_start = input
+                .char_indices()
+                .rfind(|(_, c)| c.is_whitespace())
+                .map_or(0, |(i, c)| i + c.len_utf8());
+            input.truncate(word_start);
+        }
+        Some(Action::ClearLine) => input.clear(),
+        Some(_) => {}
+        None => {
+            if let KeyCode::Char(c) = key.code {
+                if !key
+                    .modifiers
+                    .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT)
+                {
+                    input.push(c);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edited(input: &str, action: Action) -> String {
+        let mut input = input.to_string();
+        let key = KeyEvent::new(KeyCode::Null, KeyModifiers::NONE);
+        edit_input(&mut input, Some(action), key);
+        input
+    }
+
+    #[test]
+    fn delete_word_keeps_the_separator() {
+        assert_eq!(
+            edited("hello big world  ", Action::DeleteWord),
+            "hello big "
+        );
+        assert_eq!(edited("word", Action::DeleteWord), "");
+        assert_eq!(edited("hi\u{3000}there", Action::DeleteWord), "hi\u{3000}");
+    }
+
+    #[test]
+    fn long_passwords_survive_moving_to_a_bigger_buffer() {
+        let mut password = Zeroizing::new(String::with_capacity(PASSWORD_CAPACITY));
+        let key = KeyEvent::new(KeyCode::Char('é'), KeyModifiers::NONE);
+        for _ in 0..PASSWORD_CAPACITY {
+            edit_password(&mut password, None, key);
+        }
+        assert_eq!(*password, "é".repeat(PASSWORD_CAPACITY));
+        assert!(password.capacity() > PASSWORD_CAPACITY);
+    }
+
+    #[test]
+    fn unbound_keys_type_unless_modified() {
+        let mut input = "a".to_string();
+        edit_input(
+            &mut input,
+            None,
+            KeyEvent::new(KeyCode::Char('b'), KeyModifiers::NONE),
+        );
+        edit_input(
+            &mut input,
+            None,
+            KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL),
+        );
+        assert_eq!(input, "ab");
+        assert_eq!(edited("ab", Action::DeleteBackward), "a");
+        assert_eq!(edited("ab", Action::ClearLine), "");
     }
 }
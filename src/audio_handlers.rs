@@ -1,19 +1,183 @@
-use rodio::{source::Source, Decoder, OutputStream};
-use std::fs::File;
-use std::io::BufReader;
-pub fn new_message_audio() {
-    // Get a output stream handle to the default physical sound device
-    let (_stream, stream_handle) = OutputStream::try_default().unwrap();
-    // Load a sound from a file, using a path relative to Cargo.toml
-    let file = BufReader::new(File::open("audio/video_game_sound.wav").unwrap());
-    // Decode that sound file into a source
-    let source = Decoder::new(file).unwrap();
-    // Play the sound directly on the device
-    stream_handle
-        .play_raw(source.convert_samples())
-        .map_err(|err| println!("{:?}", err))
-        .ok();
-    // The sound plays in a separate audio thread,
-    // so we need to keep the main thread alive while it's playing.
-    std::thread::sleep(std::time::Duration::from_secs(1));
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use log::warn;
+use rodio::{source::Source, Decoder, OutputStream, OutputStreamHandle};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+
+// built into the binary so it plays wherever the binary is run from
+const DEFAULT_SOUND: &[u8] = include_bytes!("../audio/video_game_sound.wav");
+const DEFAULT_VOLUME: f32 = 0.8;
+
+/// Events that can play a sound
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Sound {
+    Message,
+    Mention,
+    DirectMessage,
+    Login,
+}
+
+impl Sound {
+    const ALL: [Sound; 4] = [
+        Sound::Message,
+        Sound::Mention,
+        Sound::DirectMessage,
+        Sound::Login,
+    ];
+}
+
+/// `[audio]` section of the config file
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct AudioConfig {
+    /// Turns every sound off without touching the notification rules
+    pub enabled: bool,
+    /// From 0.0 (silent) to 1.0 (the file's own volume)
+    pub volume: f32,
+    /// Sound file for each event replacing the built in sound.
+    /// Mentions, direct messages and login fall back to `message`.
+    pub message: Option<PathBuf>,
+    pub mention: Option<PathBuf>,
+    pub direct_message: Option<PathBuf>,
+    pub login: Option<PathBuf>,
+}
+
+impl Default for AudioConfig {
+    fn default() -> AudioConfig {
+        AudioConfig {
+            enabled: true,
+            volume: DEFAULT_VOLUME,
+            message: None,
+            mention: None,
+            direct_message: None,
+            login: None,
+        }
+    }
+}
+
+impl AudioConfig {
+    fn path(&self, sound: Sound) -> Option<&PathBuf> {
+        let path = match sound {
+            Sound::Message => None,
+            Sound::Mention => self.mention.as_ref(),
+            Sound::DirectMessage => self.direct_message.as_ref(),
+            Sound::Login => self.login.as_ref(),
+        };
+        path.or(self.message.as_ref())
+    }
+}
+
+enum AudioRequest {
+    Play(Sound),
+    Volume(f32),
+}
+
+/// Handle to the audio thread, cheap to call from the UI thread.
+/// Without an audio device or with audio disabled every request is dropped.
+#[derive(Default)]
+pub struct AudioPlayer {
+    sender: Option<Sender<AudioRequest>>,
+}
+
+impl AudioPlayer {
+    /// Starts the audio thread, which keeps a single output stream open for every sound
+    pub fn start(config: &AudioConfig) -> AudioPlayer {
+        if !config.enabled {
+            return AudioPlayer::default();
+        }
+        let volume = config.volume.clamp(0.0, 1.0);
+        let sounds = load_sounds(config);
+        let (sender, receiver) = unbounded();
+        let started = thread::Builder::new()
+            .name("audio thread".to_string())
+            .spawn(move || play_requests(receiver, sounds, volume));
+        if let Err(err) = started {
+            warn!("Failed to start the audio thread: {}", err);
+            return AudioPlayer::default();
+        }
+        AudioPlayer {
+            sender: Some(sender),
+        }
+    }
+
+    pub fn play(&self, sound: Sound) {
+        self.send(AudioRequest::Play(sound));
+    }
+
+    pub fn set_volume(&self, volume: f32) {
+        self.send(AudioRequest::Volume(volume.clamp(0.0, 1.0)));
+    }
+
+    // the thread is gone when there is no audio device, so failures are expected
+    fn send(&self, request: AudioRequest) {
+        if let Some(sender) = &self.sender {
+            sender.send(request).ok();
+        }
+    }
+}
+
+// Files are read once up front, each play decodes from memory.
+// Sounds without a readable file use the built in one.
+fn load_sounds(config: &AudioConfig) -> HashMap<Sound, Arc<[u8]>> {
+    let default_sound: Arc<[u8]> = DEFAULT_SOUND.into();
+    let mut files: HashMap<&PathBuf, Arc<[u8]>> = HashMap::new();
+    let mut sounds = HashMap::new();
+    for sound in Sound::ALL {
+        let path = match config.path(sound) {
+            Some(path) => path,
+            None => {
+                sounds.insert(sound, default_sound.clone());
+                continue;
+            }
+        };
+        if !files.contains_key(path) {
+            let bytes = fs::read(path).map(Arc::from).unwrap_or_else(|err| {
+                warn!("Failed to read sound {}: {}", path.display(), err);
+                default_sound.clone()
+            });
+            files.insert(path, bytes);
+        }
+        sounds.insert(sound, files[path].clone());
+    }
+    sounds
+}
+
+fn play_requests(receiver: Receiver<AudioRequest>, sounds: HashMap<Sound, Arc<[u8]>>, volume: f32) {
+    // the stream stops playing when dropped, so it lives as long as the thread
+    let (_stream, handle) = match OutputStream::try_default() {
+        Ok(output) => output,
+        Err(err) => {
+            warn!("No audio output, sounds are disabled: {}", err);
+            return;
+        }
+    };
+    let mut volume = volume;
+    for request in receiver {
+        match request {
+            AudioRequest::Volume(new_volume) => volume = new_volume,
+            AudioRequest::Play(sound) => {
+                if let Some(bytes) = sounds.get(&sound) {
+                    play(&handle, bytes.clone(), volume);
+                }
+            }
+        }
+    }
+}
+
+fn play(handle: &OutputStreamHandle, bytes: Arc<[u8]>, volume: f32) {
+    let source = match Decoder::new(Cursor::new(bytes)) {
+        Ok(source) => source,
+        Err(err) => {
+            warn!("Failed to decode sound: {}", err);
+            return;
+        }
+    };
+    if let Err(err) = handle.play_raw(source.convert_samples().amplify(volume)) {
+        warn!("Failed to play sound: {}", err);
+    }
 }
@@ -0,0 +1,525 @@
+use crate::config::{Config, Overrides};
+use crate::credentials::{CredentialStore, SigningKeys};
+use crate::db;
+use crate::export::{self, ExportError, ExportFormat};
+use crate::import::{self, ImportError};
+use crate::keymap;
+use crate::logging;
+use crate::model::models::{MessageFilter, Session, WigglesUser};
+use crate::model::route_handler;
+use crate::profiles::DEFAULT_PROFILE;
+use crate::rooms;
+use crate::send::{self, SendError};
+use crate::tail::{self, TailOptions};
+use crate::theme;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use std::env;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::Path;
+use zeroize::Zeroizing;
+
+const PASSWORD_VARIABLE: &str = "WIGGLES_PASSWORD";
+
+/// Command line arguments, running the TUI when no subcommand is given
+pub fn app() -> App<'static, 'static> {
+    App::new("wiggles")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Walkie talkie wiggles, a terminal chat over Kafka")
+        .setting(AppSettings::VersionlessSubcommands)
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .short("c")
+                .global(true)
+                .takes_value(true)
+                .help("Config file to use instead of $XDG_CONFIG_HOME/wiggles/config.toml"),
+        )
+        .arg(
+            Arg::with_name("log-level")
+                .long("log-level")
+                .global(true)
+                .takes_value(true)
+                .possible_values(&logging::LEVELS)
+                .help("Logs to stderr, or to $XDG_CACHE_HOME/wiggles/wiggles.log in the TUI [default: warn]"),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .short("p")
+                .global(true)
+                .takes_value(true)
+                .help("Profile from the config file to use, overriding WIGGLES_PROFILE"),
+        )
+        .arg(
+            Arg::with_name("database-url")
+                .long("database-url")
+                .global(true)
+                .takes_value(true)
+                .help("Overrides database.url from the config file and WIGGLES_DATABASE_URL"),
+        )
+        .arg(
+            Arg::with_name("brokers")
+                .long("brokers")
+                .global(true)
+                .takes_value(true)
+                .help("Overrides transport.brokers from the config file and WIGGLES_BROKERS"),
+        )
+        .arg(
+            Arg::with_name("topic")
+                .long("topic")
+                .global(true)
+                .takes_value(true)
+                .help("Overrides transport.topic from the config file and WIGGLES_TOPIC"),
+        )
+        .arg(
+            Arg::with_name("theme")
+                .long("theme")
+                .global(true)
+                .takes_value(true)
+                .possible_values(&theme::BUILT_IN_THEMES)
+                .help("Overrides theme.name from the config file"),
+        )
+        .arg(
+            Arg::with_name("keymap")
+                .long("keymap")
+                .global(true)
+                .takes_value(true)
+                .possible_values(&keymap::PRESETS)
+                .help("Overrides keymap.preset from the config file"),
+        )
+        .arg(
+            Arg::with_name("volume")
+                .long("volume")
+                .global(true)
+                .takes_value(true)
+                .validator(|volume| match volume.parse::<f32>() {
+                    Ok(volume) if (0.0..=1.0).contains(&volume) => Ok(()),
+                    _ => Err("expected a number from 0.0 to 1.0".to_string()),
+                })
+                .help("Overrides audio.volume from the config file"),
+        )
+        .subcommand(
+            SubCommand::with_name("tui")
+                .about("Opens the chat, the default without a subcommand")
+                .after_help(
+                    "Logging in and sending go through the HTTP server at `http.url`. \
+                     The chat runs it in the background when `http.url` is unset or points at \
+                     `http.bind`, otherwise it has to be reachable there.",
+                )
+                .arg(
+                    Arg::with_name("serve")
+                        .long("serve")
+                        .help("Also runs the HTTP server in the background when `http.url` points elsewhere"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("serve")
+                .about("Runs the HTTP server only")
+                .arg(
+                    Arg::with_name("bind")
+                        .long("bind")
+                        .takes_value(true)
+                        .help("Address to listen on, overriding http.bind [default: 127.0.0.1:8080]"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("send")
+                .about("Posts a message without opening the chat")
+                .after_help(
+                    "The password is read from WIGGLES_PASSWORD, or asked for on the terminal.\n\
+                     The message is stored through the HTTP server at `http.url`.\n\
+                     Exits with 2 for bad input, 3 when authentication fails, 4 when the message \
+                     could not be stored and 5 when it was stored but not delivered.",
+                )
+                .arg(
+                    Arg::with_name("email")
+                        .long("email")
+                        .short("u")
+                        .takes_value(true)
+                        .env("WIGGLES_EMAIL")
+                        .required(true)
+                        .help("Email of the user to send as"),
+                )
+                .arg(
+                    Arg::with_name("room")
+                        .long("room")
+                        .short("r")
+                        .takes_value(true)
+                        .default_value(rooms::DEFAULT_ROOM),
+                )
+                .arg(
+                    Arg::with_name("message")
+                        .help("Message to send, read from stdin when left out"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("tail")
+                .about("Prints messages to stdout as they arrive")
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .short("f")
+                        .takes_value(true)
+                        .possible_values(&["text", "jsonl"])
+                        .default_value("text"),
+                )
+                .arg(
+                    Arg::with_name("room")
+                        .long("room")
+                        .takes_value(true)
+                        .help("Only messages in this room, `dm:<a>:<b>` for direct messages"),
+                )
+                .arg(
+                    Arg::with_name("user")
+                        .long("user")
+                        .takes_value(true)
+                        .help("Only messages sent by this user, by name or email"),
+                )
+                .arg(
+                    Arg::with_name("history")
+                        .long("history")
+                        .help("Starts with the stored messages before following new ones"),
+                )
+                .arg(
+                    Arg::with_name("since")
+                        .long("since")
+                        .takes_value(true)
+                        .help("Starts with stored messages sent on or after YYYY-MM-DD or an RFC 3339 time"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("user")
+                .about("Manages users")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .about("Creates a user, asking for their password")
+                        .arg(Arg::with_name("name").required(true))
+                        .arg(
+                            Arg::with_name("email")
+                                .long("email")
+                                .takes_value(true)
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("password-stdin")
+                                .long("password-stdin")
+                                .help("Reads the password from the first line of stdin"),
+                        ),
+                )
+                .subcommand(SubCommand::with_name("list").about("Lists every user")),
+        )
+        .subcommand(
+            SubCommand::with_name("migrate")
+                .about("Applies database migrations the database doesn't have yet"),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Writes stored messages as JSON Lines, CSV or a transcript")
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .short("f")
+                        .takes_value(true)
+                        .possible_values(&export::FORMATS)
+                        .default_value("jsonl"),
+                )
+                .arg(
+                    Arg::with_name("room")
+                        .long("room")
+                        .takes_value(true)
+                        .help("Only messages in this room, `dm:<a>:<b>` for direct messages"),
+                )
+                .arg(
+                    Arg::with_name("user")
+                        .long("user")
+                        .takes_value(true)
+                        .help("Only messages sent by this user, by name or email"),
+                )
+                .arg(
+                    Arg::with_name("since")
+                        .long("since")
+                        .takes_value(true)
+                        .help("Only messages sent on or after YYYY-MM-DD or an RFC 3339 time"),
+                )
+                .arg(
+                    Arg::with_name("until")
+                        .long("until")
+                        .takes_value(true)
+                        .help("Only messages sent up to YYYY-MM-DD (inclusive) or before an RFC 3339 time"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .takes_value(true)
+                        .help("File to write to instead of stdout"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("credentials")
+                .about("Manages the encrypted store for transport secrets and login sessions")
+                .after_help(
+                    "The passphrase is read from WIGGLES_PASSPHRASE, or asked for on the terminal.\n\
+                     Secrets belong to the profile given with --profile, `default` without one, \
+                     and take precedence over the config file.",
+                )
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("set")
+                        .about("Stores a librdkafka property such as sasl.password, asking for its value")
+                        .arg(Arg::with_name("key").required(true))
+                        .arg(
+                            Arg::with_name("value-stdin")
+                                .long("value-stdin")
+                                .help("Reads the value from the first line of stdin"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .about("Deletes a stored property")
+                        .arg(Arg::with_name("key").required(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("list")
+                        .about("Lists stored secrets by name, never their values"),
+                )
+                .subcommand(
+                    SubCommand::with_name("logout")
+                        .about("Ends the session the TUI remembers for the profile"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("Loads messages and users from a JSON Lines archive")
+                .arg(
+                    Arg::with_name("file")
+                        .required(true)
+                        .help("Archive written by `export --format jsonl`"),
+                )
+                .arg(
+                    Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .help("Checks the archive and reports what would be imported"),
+                ),
+        )
+}
+
+pub fn export(args: &ArgMatches) -> Result<(), ExportError> {
+    let format: ExportFormat = args.value_of("format").unwrap_or("jsonl").parse()?;
+    let filter = MessageFilter {
+        room: args.value_of("room").map(str::to_string),
+        user: args.value_of("user").map(str::to_string),
+        since: args
+            .value_of("since")
+            .map(export::parse_since)
+            .transpose()?,
+        until: args
+            .value_of("until")
+            .map(export::parse_until)
+            .transpose()?,
+        visible_to: None,
+    };
+    let out: Box<dyn Write> = match args.value_of("output") {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+    let count = export::export(&filter, format, BufWriter::new(out))?;
+    eprintln!("Exported {} messages", count);
+    Ok(())
+}
+
+pub fn import(args: &ArgMatches) -> Result<(), ImportError> {
+    let path = Path::new(args.value_of("file").unwrap_or_default());
+    let report = import::import(path, args.is_present("dry-run"))?;
+    print!("{}", report);
+    Ok(())
+}
+
+/// A global argument, given before or after the subcommand
+pub fn global<'a>(matches: &'a ArgMatches, name: &str) -> Option<&'a str> {
+    matches
+        .value_of(name)
+        .or_else(|| matches.subcommand().1.and_then(|args| args.value_of(name)))
+}
+
+/// Config file settings given on the command line
+pub fn overrides(matches: &ArgMatches) -> Overrides {
+    let value = |name| global(matches, name).map(str::to_string);
+    Overrides {
+        profile: value("profile"),
+        database_url: value("database-url"),
+        brokers: value("brokers"),
+        topic: value("topic"),
+        bind: value("bind"),
+        theme: value("theme"),
+        keymap: value("keymap"),
+        volume: global(matches, "volume").and_then(|volume| volume.parse().ok()),
+    }
+}
+
+pub fn serve(bind: &str) -> Result<(), Box<dyn Error>> {
+    route_handler::run_server(bind.to_string())?;
+    Ok(())
+}
+
+pub fn send(
+    args: &ArgMatches,
+    config: &Config,
+    credentials: Option<&CredentialStore>,
+) -> Result<(), SendError> {
+    let body = send::read_body(args.value_of("message"))?;
+    let email = args.value_of("email").unwrap_or_default();
+    let password = match env::var(PASSWORD_VARIABLE) {
+        Ok(password) => Zeroizing::new(password),
+        Err(_) => rpassword::prompt_password(format!("Password for {}: ", email))
+            .map(Zeroizing::new)
+            .map_err(|_| {
+                SendError::Invalid(format!(
+                    "set {} or run from a terminal to give a password",
+                    PASSWORD_VARIABLE
+                ))
+            })?,
+    };
+    let mut keys = SigningKeys::path(&config.credentials)
+        .and_then(|path| SigningKeys::load(&path))
+        .map_err(|err| SendError::Invalid(err.to_string()))?;
+    let (api, user) = send::authenticate(&config.http.url(), email, &password)?;
+    let profile = config.profile.as_deref().unwrap_or(DEFAULT_PROFILE);
+    let room = args.value_of("room").unwrap_or(rooms::DEFAULT_ROOM);
+    let sent = send::signing_identity(&api, &user, profile, &mut keys, credentials)
+        .and_then(|signing| send::send(&api, room, body, &signing));
+    // the session only lasts for this command
+    api.log_out().ok();
+    sent.map(|_| ())
+}
+
+pub fn tail(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let format = match args.value_of("format") {
+        Some("jsonl") => ExportFormat::JsonLines,
+        _ => ExportFormat::Transcript,
+    };
+    let since = args
+        .value_of("since")
+        .map(export::parse_since)
+        .transpose()?;
+    let options = TailOptions {
+        format,
+        filter: MessageFilter {
+            room: args.value_of("room").map(str::to_string),
+            user: args.value_of("user").map(str::to_string),
+            since,
+            until: None,
+            visible_to: None,
+        },
+        history: args.is_present("history") || since.is_some(),
+    };
+    tail::tail(&options)
+}
+
+pub fn user(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    match args.subcommand() {
+        ("add", Some(args)) => {
+            let password = match args.is_present("password-stdin") {
+                true => read_stdin_line()?,
+                false => {
+                    let password = Zeroizing::new(rpassword::prompt_password("Password: ")?);
+                    let repeated = Zeroizing::new(rpassword::prompt_password("Repeat password: ")?);
+                    if repeated != password {
+                        return Err("the passwords don't match".into());
+                    }
+                    password
+                }
+            };
+            if password.is_empty() {
+                return Err("the password is empty".into());
+            }
+            let name = args.value_of("name").unwrap_or_default();
+            if !rooms::is_valid_user_name(name) {
+                return Err(format!(
+                    "invalid user name `{}`, names can't hold `:` or whitespace",
+                    name
+                )
+                .into());
+            }
+            if WigglesUser::find_by_name(name)?.is_some() {
+                return Err(format!("there already is a user named {}", name).into());
+            }
+            let mut user = WigglesUser {
+                name: name.to_string(),
+                email: args.value_of("email").unwrap_or_default().to_string(),
+                ..WigglesUser::default()
+            };
+            user.set_password(&password)?;
+            user.insert()?;
+            println!("Added {} <{}>", user.name, user.email);
+        }
+        _ => {
+            for user in WigglesUser::get()? {
+                println!("{} <{}>", user.name, user.email);
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn credentials(args: &ArgMatches, config: &Config) -> Result<(), Box<dyn Error>> {
+    let profile = config.profile.as_deref().unwrap_or(DEFAULT_PROFILE);
+    let create = args.subcommand_name() == Some("set");
+    let mut store = match CredentialStore::unlock(&config.credentials, create)? {
+        Some(store) => store,
+        None => {
+            return Err("there is no credential store yet, `credentials set` creates one".into())
+        }
+    };
+    match args.subcommand() {
+        ("set", Some(args)) => {
+            let key = args.value_of("key").unwrap_or_default();
+            let value = match args.is_present("value-stdin") {
+                true => read_stdin_line()?,
+                false => Zeroizing::new(rpassword::prompt_password(format!("{}: ", key))?),
+            };
+            store.set_transport_secret(profile, key, &value);
+            store.save()?;
+            println!("Stored {} for {}", key, profile);
+        }
+        ("remove", Some(args)) => {
+            let key = args.value_of("key").unwrap_or_default();
+            if !store.remove_transport_secret(profile, key) {
+                return Err(format!("{} has no stored {}", profile, key).into());
+            }
+            store.save()?;
+            println!("Removed {} for {}", key, profile);
+        }
+        ("logout", Some(_)) => match store.take_session(profile) {
+            Some(token) => {
+                Session::revoke(&token)?;
+                store.save()?;
+                println!("Logged out of {}", profile);
+            }
+            None => println!("{} has no remembered session", profile),
+        },
+        _ => {
+            for (profile, name) in store.secret_names() {
+                println!("{}: {}", profile, name);
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn migrate() -> Result<(), Box<dyn Error>> {
+    db::run_migrations()?;
+    Ok(())
+}
+
+// wiped when dropped, it is only used for passwords
+fn read_stdin_line() -> io::Result<Zeroizing<String>> {
+    let mut line = Zeroizing::new(String::new());
+    io::stdin().lock().read_line(&mut line)?;
+    let end = line.trim_end_matches(&['\r', '\n'][..]).len();
+    line.truncate(end);
+    Ok(line)
+}
@@ -0,0 +1,554 @@
+use crate::config::{Config, CredentialsConfig};
+use crate::e2e::{Identity, SigningIdentity};
+use crate::profiles::DEFAULT_PROFILE;
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::{env, iter};
+use thiserror::Error;
+use zeroize::{Zeroize, Zeroizing};
+
+const STORE_DIR: &str = "wiggles";
+const STORE_FILE: &str = "credentials";
+const SIGNING_KEYS_FILE: &str = "signing_keys";
+const STORE_VERSION: u32 = 1;
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 24;
+const KEY_LENGTH: usize = 32;
+const PASSPHRASE_VARIABLE: &str = "WIGGLES_PASSPHRASE";
+
+#[derive(Debug, Error)]
+pub enum CredentialError {
+    #[error("could not read the credential store {path}: {source}")]
+    Read { path: PathBuf, source: io::Error },
+    #[error("could not write the credential store {path}: {source}")]
+    Write { path: PathBuf, source: io::Error },
+    #[error("the credential store {0} is damaged or from a newer version")]
+    Corrupt(PathBuf),
+    #[error("wrong passphrase for the credential store")]
+    Passphrase,
+    #[error(
+        "no passphrase for the credential store, set {} or run from a terminal",
+        PASSPHRASE_VARIABLE
+    )]
+    NoPassphrase,
+    #[error("the passphrases don't match")]
+    Mismatch,
+    #[error("no place for the credential store, set `credentials.path` in the config file")]
+    NoPath,
+    #[error("the signing key file {0} is damaged")]
+    CorruptSigningKeys(PathBuf),
+}
+
+// What is written to disk: the salt for the key and the encrypted secrets
+#[derive(Serialize, Deserialize)]
+struct StoreFile {
+    version: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+// Decrypted contents, keyed by profile name
+#[derive(Default, Serialize, Deserialize)]
+struct Secrets {
+    /// librdkafka properties such as `sasl.password`
+    #[serde(default)]
+    transport: BTreeMap<String, BTreeMap<String, String>>,
+    /// "remember me" session tokens
+    #[serde(default)]
+    sessions: BTreeMap<String, String>,
+    /// Direct message secret keys by user name
+    #[serde(default)]
+    keys: BTreeMap<String, BTreeMap<String, String>>,
+    /// Key fingerprints of other users, pinned with `/verify`
+    #[serde(default)]
+    verified: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl Drop for Secrets {
+    fn drop(&mut self) {
+        for value in self.transport.values_mut().flat_map(|p| p.values_mut()) {
+            value.zeroize();
+        }
+        for token in self.sessions.values_mut() {
+            token.zeroize();
+        }
+        for key in self.keys.values_mut().flat_map(|p| p.values_mut()) {
+            key.zeroize();
+        }
+    }
+}
+
+/// Passphrase protected file holding transport secrets, session tokens and direct message keys.
+/// The key is derived from the passphrase with Argon2 and the contents sealed with XChaCha20-Poly1305.
+pub struct CredentialStore {
+    path: PathBuf,
+    salt: [u8; SALT_LENGTH],
+    key: Zeroizing<[u8; KEY_LENGTH]>,
+    secrets: Secrets,
+}
+
+impl CredentialStore {
+    /// `credentials.path`, or the file in the user's data directory
+    pub fn path(config: &CredentialsConfig) -> Result<PathBuf, CredentialError> {
+        match &config.path {
+            Some(path) => Ok(path.to_path_buf()),
+            None => dirs::data_dir()
+                .map(|dir| dir.join(STORE_DIR).join(STORE_FILE))
+                .ok_or(CredentialError::NoPath),
+        }
+    }
+
+    /// Opens the store when it exists, or creates an empty one when `create` is set,
+    /// asking for the passphrase either way
+    pub fn unlock(
+        config: &CredentialsConfig,
+        create: bool,
+    ) -> Result<Option<CredentialStore>, CredentialError> {
+        let path = CredentialStore::path(config)?;
+        if path.exists() {
+            let passphrase = read_passphrase("Passphrase for the credential store: ")?;
+            return CredentialStore::open(&path, &passphrase).map(Some);
+        }
+        if !create {
+            return Ok(None);
+        }
+        let passphrase = read_new_passphrase()?;
+        let store = CredentialStore::create(&path, &passphrase)?;
+        store.save()?;
+        Ok(Some(store))
+    }
+
+    pub fn create(path: &Path, passphrase: &str) -> Result<CredentialStore, CredentialError> {
+        let mut salt = [0u8; SALT_LENGTH];
+        OsRng.fill_bytes(&mut salt);
+        Ok(CredentialStore {
+            path: path.to_path_buf(),
+            salt,
+            key: derive_key(passphrase, &salt)?,
+            secrets: Secrets::default(),
+        })
+    }
+
+    pub fn open(path: &Path, passphrase: &str) -> Result<CredentialStore, CredentialError> {
+        let corrupt = || CredentialError::Corrupt(path.to_path_buf());
+        let contents = fs::read_to_string(path).map_err(|source| CredentialError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let file: StoreFile = serde_json::from_str(&contents).map_err(|_| corrupt())?;
+        if file.version != STORE_VERSION {
+            return Err(corrupt());
+        }
+        let salt: [u8; SALT_LENGTH] = base64::decode(&file.salt)
+            .ok()
+            .and_then(|salt| salt.try_into().ok())
+            .ok_or_else(corrupt)?;
+        let nonce = base64::decode(&file.nonce)
+            .ok()
+            .filter(|nonce| nonce.len() == NONCE_LENGTH)
+            .ok_or_else(corrupt)?;
+        let ciphertext = base64::decode(&file.ciphertext).map_err(|_| corrupt())?;
+
+        let key = derive_key(passphrase, &salt)?;
+        // a wrong passphrase and a tampered file look the same to the cipher
+        let plaintext = XChaCha20Poly1305::new(Key::from_slice(key.as_ref()))
+            .decrypt(XNonce::from_slice(&nonce), ciphertext.as_ref())
+            .map(Zeroizing::new)
+            .map_err(|_| CredentialError::Passphrase)?;
+        let secrets = serde_json::from_slice(&plaintext).map_err(|_| corrupt())?;
+        Ok(CredentialStore {
+            path: path.to_path_buf(),
+            salt,
+            key,
+            secrets,
+        })
+    }
+
+    /// Encrypts the secrets with a fresh nonce and replaces the file, readable by the owner only
+    pub fn save(&self) -> Result<(), CredentialError> {
+        let write_error = |source| CredentialError::Write {
+            path: self.path.to_path_buf(),
+            source,
+        };
+        let plaintext = Zeroizing::new(
+            serde_json::to_vec(&self.secrets).map_err(|err| write_error(err.into()))?,
+        );
+        let mut nonce = [0u8; NONCE_LENGTH];
+        OsRng.fill_bytes(&mut nonce);
+        let ciphertext = XChaCha20Poly1305::new(Key::from_slice(self.key.as_ref()))
+            .encrypt(XNonce::from_slice(&nonce), plaintext.as_ref())
+            .map_err(|_| write_error(io::Error::other("encryption failed")))?;
+        let file = StoreFile {
+            version: STORE_VERSION,
+            salt: base64::encode(self.salt),
+            nonce: base64::encode(nonce),
+            ciphertext: base64::encode(ciphertext),
+        };
+        let contents = serde_json::to_vec_pretty(&file).map_err(|err| write_error(err.into()))?;
+        write_private(&self.path, &contents).map_err(write_error)
+    }
+
+    /// Secret transport properties of a profile, e.g. `sasl.password`
+    pub fn transport_secrets(&self, profile: &str) -> impl Iterator<Item = (&str, &str)> {
+        self.secrets
+            .transport
+            .get(profile)
+            .into_iter()
+            .flatten()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// Profiles and the names of their stored secrets, never the values
+    pub fn secret_names(&self) -> impl Iterator<Item = (&str, String)> {
+        let transport = self
+            .secrets
+            .transport
+            .iter()
+            .flat_map(|(profile, secrets)| {
+                secrets
+                    .keys()
+                    .map(move |key| (profile.as_str(), key.to_string()))
+            });
+        let sessions = self
+            .secrets
+            .sessions
+            .keys()
+            .map(|profile| (profile.as_str(), "session".to_string()));
+        let keys = self.secrets.keys.iter().flat_map(|(profile, users)| {
+            users
+                .keys()
+                .map(move |user| (profile.as_str(), format!("direct message key of {}", user)))
+        });
+        transport.chain(sessions).chain(keys)
+    }
+
+    pub fn set_transport_secret(&mut self, profile: &str, key: &str, value: &str) {
+        let secrets = self
+            .secrets
+            .transport
+            .entry(profile.to_string())
+            .or_default();
+        if let Some(mut old) = secrets.insert(key.to_string(), value.to_string()) {
+            old.zeroize();
+        }
+    }
+
+    /// Whether the profile had the secret
+    pub fn remove_transport_secret(&mut self, profile: &str, key: &str) -> bool {
+        let secrets = match self.secrets.transport.get_mut(profile) {
+            Some(secrets) => secrets,
+            None => return false,
+        };
+        let removed = secrets.remove(key).map(|mut old| old.zeroize()).is_some();
+        if secrets.is_empty() {
+            self.secrets.transport.remove(profile);
+        }
+        removed
+    }
+
+    pub fn session(&self, profile: &str) -> Option<&str> {
+        self.secrets.sessions.get(profile).map(String::as_str)
+    }
+
+    pub fn set_session(&mut self, profile: &str, token: &str) {
+        if let Some(mut old) = self
+            .secrets
+            .sessions
+            .insert(profile.to_string(), token.to_string())
+        {
+            old.zeroize();
+        }
+    }
+
+    /// Removes the profile's session token, returning it to revoke
+    pub fn take_session(&mut self, profile: &str) -> Option<Zeroizing<String>> {
+        self.secrets.sessions.remove(profile).map(Zeroizing::new)
+    }
+
+    /// The user's direct message keypair on a profile, created on their first login from this machine
+    pub fn identity(&self, profile: &str, user: &str) -> Option<Identity> {
+        self.secrets
+            .keys
+            .get(profile)
+            .and_then(|users| users.get(user))
+            .and_then(|secret| Identity::from_secret_base64(secret))
+    }
+
+    pub fn set_identity(&mut self, profile: &str, user: &str, identity: &Identity) {
+        let users = self.secrets.keys.entry(profile.to_string()).or_default();
+        if let Some(mut old) = users.insert(user.to_string(), identity.secret_base64().to_string())
+        {
+            old.zeroize();
+        }
+    }
+
+    /// Fingerprint of another user's key as it was when it was verified
+    pub fn verified_fingerprint(&self, profile: &str, user: &str) -> Option<&str> {
+        self.secrets
+            .verified
+            .get(profile)
+            .and_then(|users| users.get(user))
+            .map(String::as_str)
+    }
+
+    pub fn set_verified(&mut self, profile: &str, user: &str, fingerprint: &str) {
+        self.secrets
+            .verified
+            .entry(profile.to_string())
+            .or_default()
+            .insert(user.to_string(), fingerprint.to_string());
+    }
+
+    /// Adds each profile's stored secrets to its transport properties.
+    /// Call before applying the environment, which still wins.
+    pub fn apply(&self, config: &mut Config) {
+        let active = config
+            .profile
+            .as_deref()
+            .unwrap_or(DEFAULT_PROFILE)
+            .to_string();
+        let transports = iter::once((active.as_str(), &mut config.transport)).chain(
+            config
+                .profiles
+                .iter_mut()
+                .map(|(name, profile)| (name.as_str(), &mut profile.transport)),
+        );
+        for (profile, transport) in transports {
+            for (key, value) in self.transport_secrets(profile) {
+                transport
+                    .properties
+                    .insert(key.to_string(), value.to_string());
+            }
+        }
+    }
+}
+
+fn derive_key(
+    passphrase: &str,
+    salt: &[u8],
+) -> Result<Zeroizing<[u8; KEY_LENGTH]>, CredentialError> {
+    let mut key = Zeroizing::new([0u8; KEY_LENGTH]);
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, key.as_mut())
+        .map_err(|_| CredentialError::Passphrase)?;
+    Ok(key)
+}
+
+/// `WIGGLES_PASSPHRASE` if set, otherwise asked for on the terminal
+pub fn read_passphrase(prompt: &str) -> Result<Zeroizing<String>, CredentialError> {
+    if let Ok(passphrase) = env::var(PASSPHRASE_VARIABLE) {
+        return Ok(Zeroizing::new(passphrase));
+    }
+    rpassword::prompt_password(prompt)
+        .map(Zeroizing::new)
+        .map_err(|_| CredentialError::NoPassphrase)
+}
+
+fn read_new_passphrase() -> Result<Zeroizing<String>, CredentialError> {
+    if let Ok(passphrase) = env::var(PASSPHRASE_VARIABLE) {
+        return Ok(Zeroizing::new(passphrase));
+    }
+    let passphrase = read_passphrase("New passphrase for the credential store: ")?;
+    let repeated = read_passphrase("Repeat the passphrase: ")?;
+    match passphrase == repeated {
+        true => Ok(passphrase),
+        false => Err(CredentialError::Mismatch),
+    }
+}
+
+/// Event signing keys by profile and user name, next to the credential store but not in it:
+/// signing has to work on every login, without asking for the store's passphrase.
+/// Like an SSH key without a passphrase the file is readable by the owner only.
+/// Whoever reads it can sign as the user, but not read their direct messages.
+pub struct SigningKeys {
+    path: PathBuf,
+    keys: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl Drop for SigningKeys {
+    fn drop(&mut self) {
+        for key in self.keys.values_mut().flat_map(|p| p.values_mut()) {
+            key.zeroize();
+        }
+    }
+}
+
+impl SigningKeys {
+    /// The file next to the credential store
+    pub fn path(config: &CredentialsConfig) -> Result<PathBuf, CredentialError> {
+        CredentialStore::path(config).map(|store| store.with_file_name(SIGNING_KEYS_FILE))
+    }
+
+    /// Reads the file, with no keys yet when it doesn't exist
+    pub fn load(path: &Path) -> Result<SigningKeys, CredentialError> {
+        let keys = match fs::read(path) {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .map_err(|_| CredentialError::CorruptSigningKeys(path.to_path_buf()))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(source) => {
+                return Err(CredentialError::Read {
+                    path: path.to_path_buf(),
+                    source,
+                })
+            }
+        };
+        Ok(SigningKeys {
+            path: path.to_path_buf(),
+            keys,
+        })
+    }
+
+    pub fn get(&self, profile: &str, user: &str) -> Option<SigningIdentity> {
+        self.keys
+            .get(profile)
+            .and_then(|users| users.get(user))
+            .and_then(|secret| SigningIdentity::from_secret_base64(secret))
+    }
+
+    pub fn set(&mut self, profile: &str, user: &str, signing: &SigningIdentity) {
+        self.keys
+            .entry(profile.to_string())
+            .or_default()
+            .insert(user.to_string(), signing.secret_base64().to_string());
+    }
+
+    pub fn save(&self) -> Result<(), CredentialError> {
+        let write_error = |source| CredentialError::Write {
+            path: self.path.to_path_buf(),
+            source,
+        };
+        let contents = Zeroizing::new(
+            serde_json::to_vec_pretty(&self.keys).map_err(|err| write_error(err.into()))?,
+        );
+        write_private(&self.path, &contents).map_err(write_error)
+    }
+}
+
+// Written next to the file and renamed over it, so a failed write keeps the old one
+fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let temporary = path.with_extension("tmp");
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(&temporary)
+        .and_then(|mut out| out.write_all(contents))
+        .and_then(|_| fs::rename(&temporary, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A store path of its own for each test, removed when dropped
+    struct TempStore(PathBuf);
+
+    impl TempStore {
+        fn new(name: &str) -> TempStore {
+            let dir = env::temp_dir().join(format!("wiggles-test-{}-{}", name, std::process::id()));
+            TempStore(dir.join(STORE_FILE))
+        }
+    }
+
+    impl Drop for TempStore {
+        fn drop(&mut self) {
+            if let Some(dir) = self.0.parent() {
+                fs::remove_dir_all(dir).ok();
+            }
+        }
+    }
+
+    #[test]
+    fn secrets_survive_saving_and_opening() {
+        let path = TempStore::new("round-trip");
+        let mut store = CredentialStore::create(&path.0, "correct horse").unwrap();
+        store.set_transport_secret("work", "sasl.password", "hunter2");
+        store.set_session("work", "token");
+        store.set_verified("work", "bob", "ab12");
+        store.save().unwrap();
+
+        let mut store = CredentialStore::open(&path.0, "correct horse").unwrap();
+        let secrets: Vec<_> = store.transport_secrets("work").collect();
+        assert_eq!(secrets, vec![("sasl.password", "hunter2")]);
+        assert_eq!(store.session("work"), Some("token"));
+        assert_eq!(store.verified_fingerprint("work", "bob"), Some("ab12"));
+        assert_eq!(
+            store.take_session("work").as_deref().map(String::as_str),
+            Some("token")
+        );
+        assert_eq!(store.session("work"), None);
+        assert!(store.remove_transport_secret("work", "sasl.password"));
+        assert!(!store.remove_transport_secret("work", "sasl.password"));
+    }
+
+    #[test]
+    fn wrong_passphrase_and_tampering_are_refused() {
+        let path = TempStore::new("tamper");
+        let store = CredentialStore::create(&path.0, "correct horse").unwrap();
+        store.save().unwrap();
+        assert!(matches!(
+            CredentialStore::open(&path.0, "battery staple"),
+            Err(CredentialError::Passphrase)
+        ));
+
+        let contents = fs::read_to_string(&path.0).unwrap();
+        let mut file: StoreFile = serde_json::from_str(&contents).unwrap();
+        let mut ciphertext = base64::decode(&file.ciphertext).unwrap();
+        ciphertext[0] ^= 1;
+        file.ciphertext = base64::encode(ciphertext);
+        fs::write(&path.0, serde_json::to_vec(&file).unwrap()).unwrap();
+        assert!(matches!(
+            CredentialStore::open(&path.0, "correct horse"),
+            Err(CredentialError::Passphrase)
+        ));
+
+        fs::write(&path.0, "not a store").unwrap();
+        assert!(matches!(
+            CredentialStore::open(&path.0, "correct horse"),
+            Err(CredentialError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn secret_names_leave_out_values() {
+        let path = TempStore::new("names");
+        let mut store = CredentialStore::create(&path.0, "correct horse").unwrap();
+        store.set_transport_secret("work", "sasl.password", "hunter2");
+        store.set_session("home", "token");
+        let names: Vec<_> = store.secret_names().collect();
+        assert_eq!(
+            names,
+            vec![
+                ("work", "sasl.password".to_string()),
+                ("home", "session".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn stored_secrets_go_to_their_profile() {
+        let path = TempStore::new("apply");
+        let mut store = CredentialStore::create(&path.0, "correct horse").unwrap();
+        store.set_transport_secret(DEFAULT_PROFILE, "sasl.password", "home secret");
+        store.set_transport_secret("work", "sasl.password", "work secret");
+        let mut config: Config = toml::from_str("[profiles.work]").unwrap();
+        store.apply(&mut config);
+        assert_eq!(config.transport.properties["sasl.password"], "home secret");
+        assert_eq!(
+            config.profiles["work"].transport.properties["sasl.password"],
+            "work secret"
+        );
+    }
+}
@@ -1,10 +1,39 @@
 use diesel::pg::PgConnection;
 use diesel::prelude::*;
-use dotenv::dotenv;
-use std::env;
+use diesel_migrations::RunMigrationsError;
+use std::io;
+use std::sync::RwLock;
+
+embed_migrations!();
+
+// the database of the profile in use, replaced when the TUI switches profiles
+static DATABASE_URL: RwLock<String> = RwLock::new(String::new());
+
+/// Sets the database new connections go to
+pub fn configure(url: &str) {
+    *DATABASE_URL.write().unwrap() = url.to_string();
+}
+
+/// Connects to a given database rather than the one in use, for threads tied to one profile
+pub fn connect(database_url: &str) -> ConnectionResult<PgConnection> {
+    PgConnection::establish(database_url)
+}
+
+/// The database new connections go to
+pub fn url() -> String {
+    DATABASE_URL.read().unwrap().clone()
+}
 
 pub fn connection() -> PgConnection {
-    dotenv().ok();
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let database_url = DATABASE_URL.read().unwrap().clone();
+    assert!(
+        !database_url.is_empty(),
+        "the database is configured at startup"
+    );
     PgConnection::establish(&database_url).expect(&format!("Error connecting to {}", database_url))
 }
+
+/// Applies the migrations built into the binary that the database doesn't have yet
+pub fn run_migrations() -> Result<(), RunMigrationsError> {
+    embedded_migrations::run_with_output(&connection(), &mut io::stdout())
+}
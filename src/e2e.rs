@@ -0,0 +1,422 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use hkdf::Hkdf;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+/// Start of an encrypted body, followed by the base64 nonce, `:` and the base64 ciphertext
+const ENVELOPE_PREFIX: &str = "e2e1:";
+const KEY_INFO: &[u8] = b"wiggles direct message v1";
+const SIGNING_KEY_INFO: &[u8] = b"wiggles event signing v1";
+const NONCE_LENGTH: usize = 24;
+// Bytes of the key hash shown as a fingerprint
+const FINGERPRINT_LENGTH: usize = 16;
+/// Shown in place of a direct message that can't be decrypted
+pub const UNREADABLE: &str = "🔒 [encrypted with a key this client doesn't have]";
+
+#[derive(Debug, Error)]
+pub enum E2eError {
+    #[error("{0} has no encryption key yet, they need to log in to the TUI once")]
+    NoKey(String),
+    #[error("this client has no key to encrypt with, log in again")]
+    NoIdentity,
+    #[error(
+        "{0}'s key changed since you verified it, compare it again with /keys and type /verify"
+    )]
+    KeyChanged(String),
+    #[error("the encryption key of {0} is invalid")]
+    InvalidKey(String),
+    #[error("the message is not encrypted")]
+    NotEncrypted,
+    #[error("the message can't be decrypted with these keys")]
+    Decrypt,
+}
+
+/// How far the key of the other participant in a direct message can be trusted
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum KeyStatus {
+    /// They haven't published a key, nothing can be sent to them
+    Missing,
+    /// Encrypted, but the fingerprint hasn't been compared yet
+    Unverified,
+    /// Matches the fingerprint pinned with `/verify`
+    Verified,
+    /// Differs from the fingerprint pinned with `/verify`
+    Changed,
+}
+
+/// What a sealed body belongs to. It is bound to the ciphertext as associated data,
+/// so a body copied into another message, room or sender doesn't decrypt.
+#[derive(Clone, Copy)]
+pub struct Envelope<'a> {
+    pub id: i32,
+    pub room: &'a str,
+    pub sender: &'a str,
+}
+
+impl Envelope<'_> {
+    fn associated_data(&self) -> Vec<u8> {
+        format!("{}\0{}\0{}", self.id, self.room, self.sender).into_bytes()
+    }
+}
+
+/// A user's X25519 keypair for direct messages.
+/// The secret only ever leaves memory encrypted, in the credential store.
+pub struct Identity {
+    secret: StaticSecret,
+    public: PublicKey,
+    signing: SigningKey,
+}
+
+impl Identity {
+    pub fn generate() -> Identity {
+        Identity::from_secret(StaticSecret::random_from_rng(OsRng))
+    }
+
+    /// Reads a secret saved with `secret_base64`
+    pub fn from_secret_base64(secret: &str) -> Option<Identity> {
+        let bytes = Zeroizing::new(base64::decode(secret).ok()?);
+        let bytes: [u8; 32] = bytes.as_slice().try_into().ok()?;
+        Some(Identity::from_secret(StaticSecret::from(bytes)))
+    }
+
+    fn from_secret(secret: StaticSecret) -> Identity {
+        let public = PublicKey::from(&secret);
+        // events used to be signed with a key derived from the secret, see `signing_identity`
+        let mut seed = Zeroizing::new([0u8; 32]);
+        Hkdf::<Sha256>::new(None, secret.as_bytes())
+            .expand(SIGNING_KEY_INFO, seed.as_mut())
+            .expect("32 bytes is a valid HKDF output length");
+        let signing = SigningKey::from_bytes(&seed);
+        Identity {
+            secret,
+            public,
+            signing,
+        }
+    }
+
+    pub fn secret_base64(&self) -> Zeroizing<String> {
+        Zeroizing::new(base64::encode(self.secret.as_bytes()))
+    }
+
+    /// Stored with the user so others can encrypt to them
+    pub fn public_base64(&self) -> String {
+        base64::encode(self.public.as_bytes())
+    }
+
+    /// The signing key derived from this secret, which earlier versions published.
+    /// Only used to carry such a key over to the signing key file.
+    pub fn signing_identity(&self) -> SigningIdentity {
+        SigningIdentity {
+            key: self.signing.clone(),
+        }
+    }
+
+    pub fn fingerprint(&self) -> String {
+        format_fingerprint(self.public.as_bytes())
+    }
+
+    /// Seals a direct message body for the other participant, who reads it with `decrypt`.
+    /// The sender can read it back too, both sides derive the same key.
+    pub fn encrypt(
+        &self,
+        peer: &str,
+        peer_key: Option<&str>,
+        envelope: Envelope,
+        body: &str,
+    ) -> Result<String, E2eError> {
+        let peer_key = parse_public_key(peer, peer_key)?;
+        let key = self.shared_key(&peer_key);
+        let mut nonce = [0u8; NONCE_LENGTH];
+        OsRng.fill_bytes(&mut nonce);
+        let payload = Payload {
+            msg: body.as_bytes(),
+            aad: &envelope.associated_data(),
+        };
+        let ciphertext = XChaCha20Poly1305::new(Key::from_slice(key.as_ref()))
+            .encrypt(XNonce::from_slice(&nonce), payload)
+            .map_err(|_| E2eError::Decrypt)?;
+        Ok(format!(
+            "{}{}:{}",
+            ENVELOPE_PREFIX,
+            base64::encode(nonce),
+            base64::encode(ciphertext)
+        ))
+    }
+
+    pub fn decrypt(
+        &self,
+        peer: &str,
+        peer_key: Option<&str>,
+        envelope: Envelope,
+        body: &str,
+    ) -> Result<String, E2eError> {
+        let (nonce, ciphertext) = body
+            .strip_prefix(ENVELOPE_PREFIX)
+            .and_then(|envelope| envelope.split_once(':'))
+            .ok_or(E2eError::NotEncrypted)?;
+        let nonce = base64::decode(nonce)
+            .ok()
+            .filter(|nonce| nonce.len() == NONCE_LENGTH)
+            .ok_or(E2eError::Decrypt)?;
+        let ciphertext = base64::decode(ciphertext).map_err(|_| E2eError::Decrypt)?;
+        let peer_key = parse_public_key(peer, peer_key)?;
+        let key = self.shared_key(&peer_key);
+        let payload = Payload {
+            msg: &ciphertext,
+            aad: &envelope.associated_data(),
+        };
+        let plaintext = XChaCha20Poly1305::new(Key::from_slice(key.as_ref()))
+            .decrypt(XNonce::from_slice(&nonce), payload)
+            .map_err(|_| E2eError::Decrypt)?;
+        String::from_utf8(plaintext).map_err(|_| E2eError::Decrypt)
+    }
+
+    // HKDF over the X25519 shared secret, salted with both public keys in a fixed order
+    fn shared_key(&self, peer: &PublicKey) -> Zeroizing<[u8; 32]> {
+        let shared = self.secret.diffie_hellman(peer);
+        let (first, second) = match self.public.as_bytes() <= peer.as_bytes() {
+            true => (self.public.as_bytes(), peer.as_bytes()),
+            false => (peer.as_bytes(), self.public.as_bytes()),
+        };
+        let salt = [first.as_slice(), second.as_slice()].concat();
+        let mut key = Zeroizing::new([0u8; 32]);
+        Hkdf::<Sha256>::new(Some(&salt), shared.as_bytes())
+            .expand(KEY_INFO, key.as_mut())
+            .expect("32 bytes is a valid HKDF output length");
+        key
+    }
+}
+
+/// The Ed25519 key a user's published events are signed with. Unlike `Identity` it needs no
+/// passphrase, so every login can sign, whether or not it uses direct messages.
+pub struct SigningIdentity {
+    key: SigningKey,
+}
+
+impl SigningIdentity {
+    pub fn generate() -> SigningIdentity {
+        let mut seed = Zeroizing::new([0u8; 32]);
+        OsRng.fill_bytes(seed.as_mut());
+        SigningIdentity {
+            key: SigningKey::from_bytes(&seed),
+        }
+    }
+
+    /// Reads a secret saved with `secret_base64`
+    pub fn from_secret_base64(secret: &str) -> Option<SigningIdentity> {
+        let bytes = Zeroizing::new(base64::decode(secret).ok()?);
+        let bytes: [u8; 32] = bytes.as_slice().try_into().ok()?;
+        Some(SigningIdentity {
+            key: SigningKey::from_bytes(&bytes),
+        })
+    }
+
+    pub fn secret_base64(&self) -> Zeroizing<String> {
+        Zeroizing::new(base64::encode(self.key.to_bytes()))
+    }
+
+    /// Stored with the user so others can check their events' signatures
+    pub fn public_base64(&self) -> String {
+        base64::encode(self.key.verifying_key().as_bytes())
+    }
+
+    /// Base64 signature over a published payload
+    pub fn sign(&self, payload: &str) -> String {
+        base64::encode(self.key.sign(payload.as_bytes()).to_bytes())
+    }
+}
+
+/// Whether a body was sealed by `Identity::encrypt`
+pub fn is_encrypted(body: &str) -> bool {
+    body.starts_with(ENVELOPE_PREFIX)
+}
+
+/// Fingerprint of a stored public key, for comparing out of band
+pub fn fingerprint(public_key: &str) -> Option<String> {
+    let bytes = base64::decode(public_key).ok()?;
+    Some(format_fingerprint(&bytes))
+}
+
+// `1a2b 3c4d …`, the start of the key's SHA-256
+fn format_fingerprint(public_key: &[u8]) -> String {
+    let hash = Sha256::digest(public_key);
+    hash[..FINGERPRINT_LENGTH]
+        .chunks(2)
+        .map(|pair| format!("{:02x}{:02x}", pair[0], pair[1]))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Whether a key is one `Identity::public_base64` could have written
+pub fn is_public_key(key: &str) -> bool {
+    parse_public_key("", Some(key)).is_ok()
+}
+
+/// Reads a key stored with `SigningIdentity::public_base64`
+pub fn parse_signing_key(key: &str) -> Option<VerifyingKey> {
+    let bytes: [u8; 32] = base64::decode(key).ok()?.try_into().ok()?;
+    VerifyingKey::from_bytes(&bytes).ok()
+}
+
+/// Whether a signature made with `SigningIdentity::sign` matches the payload and key
+pub fn verify_signature(key: &VerifyingKey, payload: &str, signature: &str) -> bool {
+    let bytes: [u8; 64] = match base64::decode(signature)
+        .ok()
+        .and_then(|s| s.try_into().ok())
+    {
+        Some(bytes) => bytes,
+        None => return false,
+    };
+    key.verify_strict(payload.as_bytes(), &Signature::from_bytes(&bytes))
+        .is_ok()
+}
+
+fn parse_public_key(peer: &str, key: Option<&str>) -> Result<PublicKey, E2eError> {
+    let key = key.ok_or_else(|| E2eError::NoKey(peer.to_string()))?;
+    let bytes: [u8; 32] = base64::decode(key)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| E2eError::InvalidKey(peer.to_string()))?;
+    Ok(PublicKey::from(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROOM: &str = "dm:alice:bob";
+
+    fn envelope(id: i32) -> Envelope<'static> {
+        Envelope {
+            id,
+            room: ROOM,
+            sender: "alice",
+        }
+    }
+
+    // Alice seals a body for Bob
+    fn sealed(alice: &Identity, bob: &Identity, body: &str) -> String {
+        alice
+            .encrypt("bob", Some(&bob.public_base64()), envelope(1), body)
+            .unwrap()
+    }
+
+    #[test]
+    fn both_participants_open_a_sealed_body() {
+        let (alice, bob) = (Identity::generate(), Identity::generate());
+        let body = sealed(&alice, &bob, "hi bob 👋");
+        assert!(is_encrypted(&body));
+        assert!(!body.contains("hi bob"));
+        let opened = bob.decrypt("alice", Some(&alice.public_base64()), envelope(1), &body);
+        assert_eq!(opened.unwrap(), "hi bob 👋");
+        let read_back = alice.decrypt("bob", Some(&bob.public_base64()), envelope(1), &body);
+        assert_eq!(read_back.unwrap(), "hi bob 👋");
+    }
+
+    #[test]
+    fn tampered_ciphertext_is_rejected() {
+        let (alice, bob) = (Identity::generate(), Identity::generate());
+        let body = sealed(&alice, &bob, "hi bob");
+        let (nonce, ciphertext) = body
+            .strip_prefix(ENVELOPE_PREFIX)
+            .and_then(|envelope| envelope.split_once(':'))
+            .unwrap();
+        let mut bytes = base64::decode(ciphertext).unwrap();
+        bytes[0] ^= 1;
+        let tampered = format!("{}{}:{}", ENVELOPE_PREFIX, nonce, base64::encode(bytes));
+        let opened = bob.decrypt(
+            "alice",
+            Some(&alice.public_base64()),
+            envelope(1),
+            &tampered,
+        );
+        assert!(matches!(opened, Err(E2eError::Decrypt)));
+    }
+
+    #[test]
+    fn body_moved_to_another_message_is_rejected() {
+        let (alice, bob) = (Identity::generate(), Identity::generate());
+        let body = sealed(&alice, &bob, "hi bob");
+        let alice_key = alice.public_base64();
+        let moved = [
+            envelope(2),
+            Envelope {
+                room: "dm:bob:carol",
+                ..envelope(1)
+            },
+            Envelope {
+                sender: "bob",
+                ..envelope(1)
+            },
+        ];
+        for envelope in moved {
+            let opened = bob.decrypt("alice", Some(&alice_key), envelope, &body);
+            assert!(matches!(opened, Err(E2eError::Decrypt)));
+        }
+    }
+
+    #[test]
+    fn other_keys_cant_open_a_sealed_body() {
+        let (alice, bob, mallory) = (
+            Identity::generate(),
+            Identity::generate(),
+            Identity::generate(),
+        );
+        let body = sealed(&alice, &bob, "hi bob");
+        let opened = mallory.decrypt("alice", Some(&alice.public_base64()), envelope(1), &body);
+        assert!(matches!(opened, Err(E2eError::Decrypt)));
+        let missing = bob.decrypt("alice", None, envelope(1), &body);
+        assert!(matches!(missing, Err(E2eError::NoKey(_))));
+        let plain = bob.decrypt("alice", Some(&alice.public_base64()), envelope(1), "hi");
+        assert!(matches!(plain, Err(E2eError::NotEncrypted)));
+    }
+
+    #[test]
+    fn identity_survives_its_stored_secret() {
+        let identity = Identity::generate();
+        let restored = Identity::from_secret_base64(&identity.secret_base64()).unwrap();
+        assert_eq!(restored.public_base64(), identity.public_base64());
+        assert_eq!(
+            restored.signing_identity().public_base64(),
+            identity.signing_identity().public_base64()
+        );
+    }
+
+    #[test]
+    fn signing_identity_survives_its_stored_secret() {
+        let signing = SigningIdentity::generate();
+        let restored = SigningIdentity::from_secret_base64(&signing.secret_base64()).unwrap();
+        assert_eq!(restored.public_base64(), signing.public_base64());
+        assert_ne!(
+            signing.public_base64(),
+            SigningIdentity::generate().public_base64()
+        );
+        assert!(SigningIdentity::from_secret_base64("too short").is_none());
+    }
+
+    #[test]
+    fn fingerprint_matches_the_published_key() {
+        let identity = Identity::generate();
+        let fingerprint = fingerprint(&identity.public_base64()).unwrap();
+        assert_eq!(fingerprint, identity.fingerprint());
+        assert_eq!(fingerprint.split(' ').count(), FINGERPRINT_LENGTH / 2);
+        assert_ne!(fingerprint, Identity::generate().fingerprint());
+    }
+
+    #[test]
+    fn signatures_only_verify_for_the_signed_payload_and_key() {
+        let signing = SigningIdentity::generate();
+        let key = parse_signing_key(&signing.public_base64()).unwrap();
+        let signature = signing.sign("payload");
+        assert!(verify_signature(&key, "payload", &signature));
+        assert!(!verify_signature(&key, "payload!", &signature));
+        assert!(!verify_signature(&key, "payload", "not a signature"));
+        let other = parse_signing_key(&SigningIdentity::generate().public_base64()).unwrap();
+        assert!(!verify_signature(&other, "payload", &signature));
+    }
+}
@@ -26,6 +26,8 @@ impl fmt::Display for CustomError {
     }
 }
 
+impl std::error::Error for CustomError {}
+
 impl From<DieselError> for CustomError {
     fn from(error: DieselError) -> CustomError {
         match error {
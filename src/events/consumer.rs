@@ -1,4 +1,6 @@
-use crate::events::utils;
+use crate::events::utils::{self, Transport};
+use crate::events::verifier::Verifier;
+use crate::events::wiggles_event::ReceivedEvent;
 use crossbeam_channel::Sender;
 
 use log::{info, warn};
@@ -30,10 +32,12 @@ impl ConsumerContext for CustomContext {
 
 // A type alias with your custom consumer can be created for convenience.
 async fn consume_and_print(
-    topics: &[&str],
-    sender: Sender<String>,
+    transport: &Transport,
+    mut verifier: Verifier,
+    sender: Sender<ReceivedEvent>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let consumer = utils::get_config_consumer()?;
+    let consumer = utils::get_config_consumer(transport)?;
+    let topics = [transport.topic()];
 
     consumer
         .subscribe(&topics.to_vec())
@@ -53,7 +57,11 @@ async fn consume_and_print(
                 };
                 info!("key: '{:?}', payload: '{}', topic: {}, partition: {}, offset: {}, timestamp: {:?}",
                       m.key(), payload, m.topic(), m.partition(), m.offset(), m.timestamp());
-                sender.clone().send(payload.to_string()).unwrap();
+                // events whose signature doesn't match their claimed author never reach the app
+                match verifier.verify(payload) {
+                    Ok(event) => sender.clone().send(event).unwrap(),
+                    Err(err) => warn!("Dropping event at offset {}: {}", m.offset(), err),
+                }
 
                 consumer.commit_message(&m, CommitMode::Async).unwrap();
             }
@@ -61,9 +69,11 @@ async fn consume_and_print(
     }
 }
 #[tokio::main]
-pub async fn start_consuming(sender: Sender<String>) -> Result<(), Box<dyn std::error::Error>> {
-    let topic = "rust-messages";
-    let topics = [topic];
-    consume_and_print(&topics, sender).await?;
+pub async fn start_consuming(
+    transport: &Transport,
+    verifier: Verifier,
+    sender: Sender<ReceivedEvent>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    consume_and_print(transport, verifier, sender).await?;
     Ok(())
 }
@@ -1,22 +1,27 @@
-use crate::events::utils;
+use crate::events::utils::{self, Transport};
 use rdkafka::producer::{FutureProducer, FutureRecord};
 use std::boxed::Box;
 use std::time::{SystemTime, UNIX_EPOCH};
+/// Publishes a payload and waits until the broker confirms delivery
 #[tokio::main]
-pub async fn produce_event(message: String) -> Result<(), Box<dyn std::error::Error>> {
-    let config = utils::get_config_producer()?;
-    let producer: &FutureProducer = &config.create().expect("producer error");
-    let topic = String::from("rust-messages");
+pub async fn produce_event(
+    transport: &Transport,
+    message: String,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = utils::get_config_producer(transport)?;
+    let producer: &FutureProducer = &config.create()?;
+    let topic = transport.topic();
     let i = 0_usize;
-    let _result = producer
+    producer
         .send_result(
-            FutureRecord::to(&topic)
+            FutureRecord::to(topic)
                 .key(&i.to_string())
                 .payload(&message)
                 .timestamp(now()),
         )
-        .unwrap()
-        .await;
+        .map_err(|(err, _)| err)?
+        .await?
+        .map_err(|(err, _)| err)?;
     Ok(())
 }
 
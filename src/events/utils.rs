@@ -1,21 +1,80 @@
+use crate::config::{ConfigError, TransportConfig};
 use log::info;
 use rdkafka::config::{ClientConfig, RDKafkaLogLevel};
 use rdkafka::consumer::{ConsumerContext, Rebalance, StreamConsumer};
 use rdkafka::error::KafkaResult;
 use rdkafka::{ClientContext, TopicPartitionList};
-use std::env::{self};
+use std::collections::BTreeMap;
 use std::error::Error;
+use std::sync::{Arc, RwLock};
+use zeroize::Zeroize;
 
-pub fn get_config_producer() -> Result<ClientConfig, Box<dyn Error>> {
-    let mut kafka_config = ClientConfig::new();
-    let env_vars = EnvVars::get_env_vars();
-    kafka_config
-        .set("bootstrap.servers", env_vars.brokers)
-        .set("session.timeout.ms", "4500")
-        .set("security.protocol", "SASL_SSL")
-        .set("sasl.mechanisms", env_vars.sasl_mechanism)
-        .set("sasl.username", env_vars.sasl_username)
-        .set("sasl.password", env_vars.sasl_password);
+// the transport of the profile in use, replaced when the TUI switches profiles
+static ACTIVE: RwLock<Option<Arc<Transport>>> = RwLock::new(None);
+
+/// A Kafka cluster with every client property resolved
+pub struct Transport {
+    topic: String,
+    properties: BTreeMap<String, String>,
+}
+
+impl Transport {
+    pub fn new(config: &TransportConfig) -> Result<Transport, ConfigError> {
+        Ok(Transport {
+            topic: config.topic.to_string(),
+            properties: config.client_properties()?,
+        })
+    }
+
+    /// Topic every event is published to
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    // The defaults are overridden by anything configured
+    fn client_config(&self, defaults: &[(&str, &str)]) -> ClientConfig {
+        let mut kafka_config = ClientConfig::new();
+        for (key, value) in defaults {
+            kafka_config.set(*key, *value);
+        }
+        for (key, value) in &self.properties {
+            kafka_config.set(key, value);
+        }
+        kafka_config
+    }
+}
+
+impl Drop for Transport {
+    fn drop(&mut self) {
+        for value in self.properties.values_mut() {
+            value.zeroize();
+        }
+    }
+}
+
+/// Sets the cluster producers and consumers connect to at startup
+pub fn configure(config: &TransportConfig) -> Result<(), ConfigError> {
+    activate(Arc::new(Transport::new(config)?));
+    Ok(())
+}
+
+pub fn activate(transport: Arc<Transport>) {
+    *ACTIVE.write().unwrap() = Some(transport);
+}
+
+/// Transport of the profile in use
+pub fn active() -> Arc<Transport> {
+    ACTIVE
+        .read()
+        .unwrap()
+        .clone()
+        .expect("the transport is configured at startup")
+}
+
+pub fn get_config_producer(transport: &Transport) -> Result<ClientConfig, Box<dyn Error>> {
+    let mut kafka_config = transport.client_config(&[("session.timeout.ms", "4500")]);
+    // only consumers join the group
+    kafka_config.remove("group.id");
 
     Ok(kafka_config)
 }
@@ -35,41 +94,17 @@ impl ConsumerContext for CustomContext {
     }
 }
 
-struct EnvVars {
-    brokers: String,
-    sasl_mechanism: String,
-    sasl_username: String,
-    sasl_password: String,
-    group_id: String,
-}
-impl EnvVars {
-    fn get_env_vars() -> EnvVars {
-        let env_vars = EnvVars {
-            brokers: env::var("BOOTSTRAP_SERVER").expect("broker must be set"),
-            sasl_mechanism: env::var("SASL_MECHANISM").expect("sasl mech must be set"),
-            sasl_username: env::var("SASL_USERNAME").expect("sasl username must be set"),
-            sasl_password: env::var("SASL_PASSWORD").expect("sasl password must be set"),
-            group_id: env::var("GROUP_ID").expect("group id must be set"),
-        };
-
-        env_vars
-    }
-}
-pub fn get_config_consumer() -> Result<StreamConsumer<CustomContext>, Box<dyn Error>> {
+pub fn get_config_consumer(
+    transport: &Transport,
+) -> Result<StreamConsumer<CustomContext>, Box<dyn Error>> {
     let context = CustomContext;
     type LoggingConsumer = StreamConsumer<CustomContext>;
-    let env_vars = EnvVars::get_env_vars();
-    let kafka_config: LoggingConsumer = ClientConfig::new()
-        .set("enable.auto.commit", "true")
-        .set("bootstrap.servers", env_vars.brokers)
-        .set("group.id", env_vars.group_id)
-        .set("session.timeout.ms", "6000")
-        .set("security.protocol", "SASL_SSL")
-        .set("sasl.mechanisms", env_vars.sasl_mechanism)
-        .set("sasl.username", env_vars.sasl_username)
-        .set("sasl.password", env_vars.sasl_password)
+    let kafka_config: LoggingConsumer = transport
+        .client_config(&[
+            ("enable.auto.commit", "true"),
+            ("session.timeout.ms", "6000"),
+        ])
         .set_log_level(RDKafkaLogLevel::Debug)
-        .create_with_context(context)
-        .expect("Consumer creation failed");
+        .create_with_context(context)?;
     Ok(kafka_config)
 }
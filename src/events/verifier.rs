@@ -0,0 +1,262 @@
+use crate::db;
+use crate::e2e;
+use crate::error_handler::CustomError;
+use crate::events::wiggles_event::{self, EventSignature, ReceivedEvent, WigglesEvent};
+use crate::model::models::WigglesUser;
+use diesel::pg::PgConnection;
+use ed25519_dalek::VerifyingKey;
+use log::warn;
+use std::collections::HashMap;
+use thiserror::Error;
+
+/// Why an event from the transport was dropped
+#[derive(Debug, Error)]
+pub enum VerifyError {
+    #[error("malformed event: {0}")]
+    Malformed(#[from] serde_json::Error),
+    #[error("event claiming to be from {0} has a signature that doesn't match their key")]
+    Forged(String),
+    #[error("event claiming to be from {0} is unsigned, but they sign their events")]
+    Unsigned(String),
+}
+
+/// A user as far as their signatures are concerned
+#[derive(Clone)]
+struct Signer {
+    name: String,
+    // None for users who haven't published a key yet
+    key: Option<VerifyingKey>,
+}
+
+/// Checks events against the signing key their claimed author published.
+/// Signed events name their signer by email, which is looked up and has to be the claimed author.
+/// Keys are cached, and looked up again when a signature doesn't match in case the author has a new one.
+/// Authors without a key are looked up on every unsigned event, in case they have published one since.
+pub struct Verifier {
+    database_url: String,
+    connection: Option<PgConnection>,
+    // by email, None for emails no user has
+    signers: HashMap<String, Option<Signer>>,
+    // by name, for events that come unsigned. Only published keys, see above
+    keys: HashMap<String, VerifyingKey>,
+}
+
+impl Verifier {
+    /// Looks keys up in the consumer's own database, which stays put when the TUI switches profiles
+    pub fn new(database_url: &str) -> Verifier {
+        Verifier {
+            database_url: database_url.to_string(),
+            connection: None,
+            signers: HashMap::new(),
+            keys: HashMap::new(),
+        }
+    }
+
+    /// Parses a transport payload, failing when the signature gives the claimed author away.
+    /// Events of authors without a key, or whose key can't be looked up, pass as unverified.
+    pub fn verify(&mut self, payload: &str) -> Result<ReceivedEvent, VerifyError> {
+        let (signed, signature) = wiggles_event::split_signature(payload);
+        let event = WigglesEvent::parse(&signed)?;
+        let author = event.author().to_string();
+
+        let signature = match signature {
+            Some(signature) => signature,
+            None => {
+                return match self.key(&author) {
+                    Ok(Some(_)) => Err(VerifyError::Unsigned(author)),
+                    Ok(None) => unverified(event),
+                    Err(err) => {
+                        warn!("Can't look up the signing key of {}: {}", author, err);
+                        unverified(event)
+                    }
+                }
+            }
+        };
+        // a key that is new or was replaced gets one more lookup before the event counts as forged
+        for refresh in [false, true] {
+            match self.signer(&signature.signer, refresh) {
+                Ok(Some(signer)) if is_signed_by(&signer, &event, &signed, &signature) => {
+                    return Ok(ReceivedEvent {
+                        event,
+                        verified: true,
+                    });
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    warn!("Can't look up the signing key of {}: {}", author, err);
+                    return unverified(event);
+                }
+            }
+        }
+        Err(VerifyError::Forged(author))
+    }
+
+    fn signer(&mut self, email: &str, refresh: bool) -> Result<Option<Signer>, CustomError> {
+        if let (false, Some(signer)) = (refresh, self.signers.get(email)) {
+            return Ok(signer.clone());
+        }
+        let signer = self
+            .lookup(|conn| WigglesUser::find_by_email_on(conn, email))?
+            .map(|user| Signer {
+                key: parse_key(&user),
+                name: user.name,
+            });
+        self.signers.insert(email.to_string(), signer.clone());
+        Ok(signer)
+    }
+
+    fn key(&mut self, name: &str) -> Result<Option<VerifyingKey>, CustomError> {
+        if let Some(key) = self.keys.get(name) {
+            return Ok(Some(*key));
+        }
+        let key = self
+            .lookup(|conn| WigglesUser::find_by_name_on(conn, name))?
+            .and_then(|user| parse_key(&user));
+        if let Some(key) = key {
+            self.keys.insert(name.to_string(), key);
+        }
+        Ok(key)
+    }
+
+    fn lookup<T>(
+        &mut self,
+        query: impl FnOnce(&PgConnection) -> Result<T, CustomError>,
+    ) -> Result<T, CustomError> {
+        if self.connection.is_none() {
+            let connection = db::connect(&self.database_url)
+                .map_err(|err| CustomError::new(500, err.to_string()))?;
+            self.connection = Some(connection);
+        }
+        let result = query(self.connection.as_ref().unwrap());
+        if result.is_err() {
+            // reconnect next time, the connection may be what failed
+            self.connection = None;
+        }
+        result
+    }
+}
+
+// The signer has to be the claimed author, a message's stored author too,
+// and their key has to match the signature
+fn is_signed_by(
+    signer: &Signer,
+    event: &WigglesEvent,
+    signed: &str,
+    signature: &EventSignature,
+) -> bool {
+    let input = wiggles_event::signing_input(&signature.signer, signed);
+    let is_author = signer.name == event.author()
+        && event
+            .author_email()
+            .is_none_or(|email| email == signature.signer);
+    match &signer.key {
+        Some(key) => is_author && e2e::verify_signature(key, &input, &signature.signature),
+        None => false,
+    }
+}
+
+fn parse_key(user: &WigglesUser) -> Option<VerifyingKey> {
+    user.signing_key.as_deref().and_then(e2e::parse_signing_key)
+}
+
+fn unverified(event: WigglesEvent) -> Result<ReceivedEvent, VerifyError> {
+    Ok(ReceivedEvent {
+        event,
+        verified: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::e2e::SigningIdentity;
+    use crate::events::wiggles_event::TypingEvent;
+    use crate::model::models::Message;
+
+    struct Signed {
+        event: WigglesEvent,
+        payload: String,
+        signature: EventSignature,
+    }
+
+    fn signer(name: &str, identity: &SigningIdentity) -> Signer {
+        Signer {
+            name: name.to_string(),
+            key: e2e::parse_signing_key(&identity.public_base64()),
+        }
+    }
+
+    fn sign(event: WigglesEvent, identity: &SigningIdentity, email: &str) -> Signed {
+        let (payload, signature) =
+            wiggles_event::split_signature(&event.to_signed_payload(identity, email));
+        Signed {
+            event,
+            payload,
+            signature: signature.unwrap(),
+        }
+    }
+
+    // A typing event from `author`, signed with `identity` as `email`
+    fn typing(author: &str, identity: &SigningIdentity, email: &str) -> Signed {
+        sign(
+            WigglesEvent::Typing(TypingEvent::new(author, "general")),
+            identity,
+            email,
+        )
+    }
+
+    fn is_signed(signer: &Signer, signed: &Signed) -> bool {
+        is_signed_by(signer, &signed.event, &signed.payload, &signed.signature)
+    }
+
+    #[test]
+    fn event_signed_by_its_author_verifies() {
+        let alice = SigningIdentity::generate();
+        let signed = typing("alice", &alice, "alice@example.com");
+        assert_eq!(signed.signature.signer, "alice@example.com");
+        assert!(is_signed(&signer("alice", &alice), &signed));
+    }
+
+    #[test]
+    fn signer_has_to_be_the_claimed_author() {
+        // mallory signs with their own key, claiming to be alice
+        let mallory = SigningIdentity::generate();
+        let signed = typing("alice", &mallory, "mallory@example.com");
+        assert!(!is_signed(&signer("mallory", &mallory), &signed));
+    }
+
+    #[test]
+    fn signer_has_to_be_the_stored_author_of_a_message() {
+        let alice = SigningIdentity::generate();
+        let message = Message {
+            id: 1,
+            name: "alice".to_string(),
+            body: "hi".to_string(),
+            published: true,
+            room: "general".to_string(),
+            created_at: chrono::Utc::now(),
+            author_email: "alice@example.org".to_string(),
+        };
+        let signed = sign(WigglesEvent::Message(message), &alice, "alice@example.com");
+        assert!(!is_signed(&signer("alice", &alice), &signed));
+    }
+
+    #[test]
+    fn signature_covers_the_signer() {
+        let alice = SigningIdentity::generate();
+        let mut signed = typing("alice", &alice, "alice@example.com");
+        signed.signature.signer = "alice@example.org".to_string();
+        assert!(!is_signed(&signer("alice", &alice), &signed));
+    }
+
+    #[test]
+    fn signer_without_a_key_never_verifies() {
+        let alice = SigningIdentity::generate();
+        let signed = typing("alice", &alice, "alice@example.com");
+        let keyless = Signer {
+            name: "alice".to_string(),
+            key: None,
+        };
+        assert!(!is_signed(&keyless, &signed));
+    }
+}
@@ -0,0 +1,195 @@
+use crate::e2e::SigningIdentity;
+use crate::model::models::Message;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+/// Everything clients publish to each other over the transport
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WigglesEvent {
+    Message(Message),
+    Presence(PresenceEvent),
+    Typing(TypingEvent),
+    Read(ReadEvent),
+}
+
+// ordered so sorting by status lists online users first
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceStatus {
+    Online,
+    Away,
+    Offline,
+}
+
+/// Heartbeat a client publishes to announce its user's status
+#[derive(Serialize, Deserialize)]
+pub struct PresenceEvent {
+    pub name: String,
+    pub status: PresenceStatus,
+    /// Milliseconds since the unix epoch
+    pub sent_at: i64,
+}
+
+impl PresenceEvent {
+    pub fn new(name: &str, status: PresenceStatus) -> PresenceEvent {
+        PresenceEvent {
+            name: name.to_string(),
+            status,
+            sent_at: Utc::now().timestamp_millis(),
+        }
+    }
+}
+
+/// Ephemeral notice that a user is writing in a room, never stored
+#[derive(Serialize, Deserialize)]
+pub struct TypingEvent {
+    pub name: String,
+    pub room: String,
+    /// Milliseconds since the unix epoch
+    pub sent_at: i64,
+}
+
+impl TypingEvent {
+    pub fn new(name: &str, room: &str) -> TypingEvent {
+        TypingEvent {
+            name: name.to_string(),
+            room: room.to_string(),
+            sent_at: Utc::now().timestamp_millis(),
+        }
+    }
+}
+
+/// A payload wrapped with its author's signature, kept as a string so the signed bytes survive.
+/// The signer is the author's email, which unlike their name identifies them for good.
+#[derive(Serialize, Deserialize)]
+struct SignedPayload {
+    signed: String,
+    signer: String,
+    signature: String,
+}
+
+/// Who signed a payload from the transport, and their signature over it
+pub struct EventSignature {
+    pub signer: String,
+    pub signature: String,
+}
+
+/// An event from the transport whose signature, if any, matched its claimed author
+pub struct ReceivedEvent {
+    pub event: WigglesEvent,
+    /// False when the author has no signing key yet, or it couldn't be looked up
+    pub verified: bool,
+}
+
+/// A user has read a room up to a message, for "seen by" indicators
+#[derive(Serialize, Deserialize)]
+pub struct ReadEvent {
+    pub name: String,
+    pub room: String,
+    pub message_id: i32,
+}
+
+impl WigglesEvent {
+    /// Parses a transport payload. Clients predating events publish bare messages.
+    pub fn parse(payload: &str) -> Result<WigglesEvent, serde_json::Error> {
+        serde_json::from_str(payload).or_else(|err| {
+            serde_json::from_str(payload)
+                .map(WigglesEvent::Message)
+                .map_err(|_| err)
+        })
+    }
+
+    pub fn to_payload(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    /// The payload signed with the author's key, `signer` being their email
+    pub fn to_signed_payload(&self, identity: &SigningIdentity, signer: &str) -> String {
+        let signed = self.to_payload();
+        let signature = identity.sign(&signing_input(signer, &signed));
+        serde_json::to_string(&SignedPayload {
+            signed,
+            signer: signer.to_string(),
+            signature,
+        })
+        .unwrap()
+    }
+
+    /// Name of the user the event claims to come from
+    pub fn author(&self) -> &str {
+        match self {
+            WigglesEvent::Message(message) => &message.name,
+            WigglesEvent::Presence(presence) => &presence.name,
+            WigglesEvent::Typing(typing) => &typing.name,
+            WigglesEvent::Read(read) => &read.name,
+        }
+    }
+
+    /// Email of the user a message is stored for, other events only name their author
+    pub fn author_email(&self) -> Option<&str> {
+        match self {
+            WigglesEvent::Message(message) => Some(&message.author_email),
+            _ => None,
+        }
+    }
+}
+
+/// Splits a transport payload into the event payload and its signature, when it has one
+pub fn split_signature(payload: &str) -> (String, Option<EventSignature>) {
+    match serde_json::from_str::<SignedPayload>(payload) {
+        Ok(SignedPayload {
+            signed,
+            signer,
+            signature,
+        }) => (signed, Some(EventSignature { signer, signature })),
+        Err(_) => (payload.to_string(), None),
+    }
+}
+
+/// What the signature covers, the signer along with the payload
+pub fn signing_input(signer: &str, signed: &str) -> String {
+    serde_json::to_string(&(signer, signed)).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_round_trip_through_their_payload() {
+        let payload =
+            WigglesEvent::Presence(PresenceEvent::new("alice", PresenceStatus::Away)).to_payload();
+        assert!(payload.contains("\"type\":\"presence\""));
+        match WigglesEvent::parse(&payload).unwrap() {
+            WigglesEvent::Presence(presence) => {
+                assert_eq!(presence.name, "alice");
+                assert_eq!(presence.status, PresenceStatus::Away);
+            }
+            _ => panic!("expected a presence event"),
+        }
+    }
+
+    #[test]
+    fn bare_messages_parse_as_message_events() {
+        let payload = r#"{"id":1,"name":"alice","body":"hi","published":true}"#;
+        let event = WigglesEvent::parse(payload).unwrap();
+        assert_eq!(event.author(), "alice");
+        match event {
+            WigglesEvent::Message(message) => {
+                assert_eq!(message.room, "general");
+                assert_eq!(message.author_email, "");
+            }
+            _ => panic!("expected a message event"),
+        }
+        assert!(WigglesEvent::parse("{\"type\":\"wave\"}").is_err());
+    }
+
+    #[test]
+    fn unsigned_payloads_have_no_signature() {
+        let payload = WigglesEvent::Typing(TypingEvent::new("alice", "general")).to_payload();
+        let (signed, signature) = split_signature(&payload);
+        assert_eq!(signed, payload);
+        assert!(signature.is_none());
+    }
+}
@@ -0,0 +1,252 @@
+use crate::error_handler::CustomError;
+use crate::model::models::{Message, MessageFilter};
+use crate::rooms;
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use std::io::{self, Write};
+use std::str::FromStr;
+use thiserror::Error;
+
+pub const FORMATS: [&str; 3] = ["jsonl", "csv", "transcript"];
+const DATE_FORMAT: &str = "%Y-%m-%d";
+const TRANSCRIPT_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S UTC";
+
+#[derive(Debug, Error)]
+pub enum ExportError {
+    #[error("unknown export format `{0}`, expected jsonl, csv or transcript")]
+    Format(String),
+    #[error("invalid date `{0}`, expected YYYY-MM-DD or an RFC 3339 timestamp")]
+    Date(String),
+    #[error("could not load messages: {0}")]
+    Load(CustomError),
+    #[error("could not write the export: {0}")]
+    Io(#[from] io::Error),
+    #[error("could not write the export: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("could not write the export: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// One JSON `Message` per line, the format the importer reads
+    JsonLines,
+    Csv,
+    /// Human readable chat log
+    Transcript,
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::JsonLines => "application/x-ndjson",
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Transcript => "text/plain; charset=utf-8",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::JsonLines => "jsonl",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Transcript => "txt",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = ExportError;
+
+    fn from_str(value: &str) -> Result<ExportFormat, ExportError> {
+        match value.to_lowercase().as_str() {
+            "jsonl" | "json" | "ndjson" => Ok(ExportFormat::JsonLines),
+            "csv" => Ok(ExportFormat::Csv),
+            "transcript" | "text" | "txt" => Ok(ExportFormat::Transcript),
+            _ => Err(ExportError::Format(value.to_string())),
+        }
+    }
+}
+
+/// Start of a date range, from `YYYY-MM-DD` (midnight UTC) or an RFC 3339 timestamp
+pub fn parse_since(value: &str) -> Result<DateTime<Utc>, ExportError> {
+    parse_date(value, false)
+}
+
+/// End of a date range. A plain date includes the whole day.
+pub fn parse_until(value: &str) -> Result<DateTime<Utc>, ExportError> {
+    parse_date(value, true)
+}
+
+fn parse_date(value: &str, end_of_day: bool) -> Result<DateTime<Utc>, ExportError> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    let date = NaiveDate::parse_from_str(value, DATE_FORMAT)
+        .map_err(|_| ExportError::Date(value.to_string()))?;
+    let start = Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap());
+    match end_of_day {
+        true => Ok(start + Duration::days(1)),
+        false => Ok(start),
+    }
+}
+
+/// Loads the messages matching the filter and writes them out in the given format
+pub fn export<W: Write>(
+    filter: &MessageFilter,
+    format: ExportFormat,
+    out: W,
+) -> Result<usize, ExportError> {
+    let messages = Message::filtered(filter).map_err(ExportError::Load)?;
+    write_messages(&messages, format, out)?;
+    Ok(messages.len())
+}
+
+pub fn write_messages<W: Write>(
+    messages: &[Message],
+    format: ExportFormat,
+    mut out: W,
+) -> Result<(), ExportError> {
+    match format {
+        ExportFormat::JsonLines => {
+            for message in messages {
+                serde_json::to_writer(&mut out, message)?;
+                out.write_all(b"\n")?;
+            }
+        }
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(&mut out);
+            writer.write_record(["id", "created_at", "room", "name", "body"])?;
+            for message in messages {
+                writer.write_record([
+                    message.id.to_string().as_str(),
+                    message.created_at.to_rfc3339().as_str(),
+                    message.room.as_str(),
+                    message.name.as_str(),
+                    message.body.as_str(),
+                ])?;
+            }
+            writer.flush()?;
+        }
+        ExportFormat::Transcript => {
+            for message in messages {
+                write_transcript_entry(&mut out, message)?;
+            }
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+// [2022-08-01 12:00:00 UTC] #general <alice> hello
+// continuation lines are indented under the first one
+fn write_transcript_entry<W: Write>(out: &mut W, message: &Message) -> io::Result<()> {
+    let room = match rooms::dm_participants(&message.room) {
+        Some((first, second)) => format!("@{}+{}", first, second),
+        None => format!("#{}", message.room),
+    };
+    let mut lines = message.body.lines();
+    writeln!(
+        out,
+        "[{}] {} <{}> {}",
+        message.created_at.format(TRANSCRIPT_TIME_FORMAT),
+        room,
+        message.name,
+        lines.next().unwrap_or_default()
+    )?;
+    for line in lines {
+        writeln!(out, "    {}", line)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(room: &str, body: &str) -> Message {
+        Message {
+            id: 7,
+            name: "alice".to_string(),
+            body: body.to_string(),
+            published: true,
+            room: room.to_string(),
+            created_at: Utc.with_ymd_and_hms(2022, 8, 1, 12, 0, 0).unwrap(),
+            author_email: "alice@example.com".to_string(),
+        }
+    }
+
+    fn written(messages: &[Message], format: ExportFormat) -> String {
+        let mut out = Vec::new();
+        write_messages(messages, format, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn formats_parse_with_their_aliases() {
+        assert_eq!(
+            "JSONL".parse::<ExportFormat>().unwrap(),
+            ExportFormat::JsonLines
+        );
+        assert_eq!(
+            "ndjson".parse::<ExportFormat>().unwrap(),
+            ExportFormat::JsonLines
+        );
+        assert_eq!("csv".parse::<ExportFormat>().unwrap(), ExportFormat::Csv);
+        assert_eq!(
+            "txt".parse::<ExportFormat>().unwrap(),
+            ExportFormat::Transcript
+        );
+        assert!(matches!(
+            "xml".parse::<ExportFormat>(),
+            Err(ExportError::Format(_))
+        ));
+    }
+
+    #[test]
+    fn plain_dates_cover_whole_days() {
+        let midnight = Utc.with_ymd_and_hms(2022, 8, 1, 0, 0, 0).unwrap();
+        assert_eq!(parse_since("2022-08-01").unwrap(), midnight);
+        assert_eq!(
+            parse_until("2022-08-01").unwrap(),
+            midnight + Duration::days(1)
+        );
+    }
+
+    #[test]
+    fn timestamps_keep_their_time() {
+        let expected = Utc.with_ymd_and_hms(2022, 8, 1, 10, 30, 0).unwrap();
+        assert_eq!(parse_until("2022-08-01T12:30:00+02:00").unwrap(), expected);
+        assert!(matches!(
+            parse_since("yesterday"),
+            Err(ExportError::Date(_))
+        ));
+        assert!(matches!(
+            parse_since("2022-13-01"),
+            Err(ExportError::Date(_))
+        ));
+    }
+
+    #[test]
+    fn transcript_indents_continuation_lines() {
+        let messages = [
+            message("general", "hello\nworld"),
+            message("dm:alice:bob", "hi"),
+        ];
+        assert_eq!(
+            written(&messages, ExportFormat::Transcript),
+            "[2022-08-01 12:00:00 UTC] #general <alice> hello\n    world\n\
+             [2022-08-01 12:00:00 UTC] @alice+bob <alice> hi\n"
+        );
+    }
+
+    #[test]
+    fn csv_quotes_bodies_and_json_lines_read_back() {
+        let messages = [message("general", "hello, \"world\"")];
+        let csv = written(&messages, ExportFormat::Csv);
+        assert!(csv.starts_with("id,created_at,room,name,body\n"));
+        assert!(csv.contains("alice,\"hello, \"\"world\"\"\""));
+        let jsonl = written(&messages, ExportFormat::JsonLines);
+        let read: Message = serde_json::from_str(jsonl.trim_end()).unwrap();
+        assert_eq!(read.body, "hello, \"world\"");
+        assert_eq!(read.author_email, "alice@example.com");
+    }
+}
@@ -0,0 +1,488 @@
+use crate::error_handler::CustomError;
+use crate::model::models::{self, Message, WigglesUser};
+use crate::rooms;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::mem;
+use std::path::Path;
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+#[derive(Debug, Error)]
+pub enum ImportError {
+    #[error("could not read the archive: {0}")]
+    Read(#[from] io::Error),
+    #[error("the archive has invalid records, nothing was imported:\n{}", .0.join("\n"))]
+    Invalid(Vec<String>),
+    #[error("could not import: {0}")]
+    Database(CustomError),
+}
+
+impl From<CustomError> for ImportError {
+    fn from(err: CustomError) -> ImportError {
+        ImportError::Database(err)
+    }
+}
+
+/// A line of a JSON Lines archive: a `Message` as the exporter writes it, or a user
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Record {
+    Message(Message),
+    User(ImportedUser),
+}
+
+struct Archive {
+    /// Users with the line they're on, for reporting conflicts
+    users: Vec<(usize, ImportedUser)>,
+    messages: Vec<Message>,
+}
+
+#[derive(Deserialize)]
+struct ImportedUser {
+    id: i32,
+    name: String,
+    email: String,
+    /// A PHC string as stored, older archives have the password in plain text
+    #[serde(default)]
+    password: String,
+}
+
+/// What an import wrote, or would write on a dry run
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub messages_imported: usize,
+    /// Messages whose id is already stored or appeared earlier in the archive
+    pub duplicate_messages: usize,
+    pub users_created: Vec<String>,
+    /// Archive user names replaced by the name of the stored user with the same email
+    pub users_remapped: Vec<(String, String)>,
+}
+
+impl fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let verb = match self.dry_run {
+            true => "Would import",
+            false => "Imported",
+        };
+        writeln!(
+            f,
+            "{} {} messages, skipped {} duplicates",
+            verb, self.messages_imported, self.duplicate_messages
+        )?;
+        if !self.users_created.is_empty() {
+            writeln!(f, "New users: {}", self.users_created.join(", "))?;
+        }
+        for (from, to) in &self.users_remapped {
+            writeln!(f, "Messages from {} are attributed to {}", from, to)?;
+        }
+        Ok(())
+    }
+}
+
+/// Imports a JSON Lines archive in a single transaction.
+/// Every record is checked first, so an invalid archive writes nothing.
+pub fn import(path: &Path, dry_run: bool) -> Result<ImportReport, ImportError> {
+    let archive = read_archive(path)?;
+    let plan = plan(archive, &WigglesUser::get()?, &Message::ids()?)?;
+    if !dry_run {
+        models::import_history(&plan.users, &plan.messages)?;
+    }
+    Ok(ImportReport {
+        dry_run,
+        ..plan.report
+    })
+}
+
+/// What an archive adds to the stored users and messages
+struct Plan {
+    report: ImportReport,
+    users: Vec<WigglesUser>,
+    messages: Vec<Message>,
+}
+
+// Matches the archive's users and messages up with the stored ones, without writing anything
+fn plan(
+    archive: Archive,
+    stored_users: &[WigglesUser],
+    stored_ids: &HashSet<i32>,
+) -> Result<Plan, ImportError> {
+    let Archive { users, messages } = archive;
+    let mut report = ImportReport::default();
+    let mut problems = Vec::new();
+
+    // users are matched by email, the archive's name for them may differ
+    let stored_by_email: HashMap<&str, &str> = stored_users
+        .iter()
+        .map(|user| (user.email.as_str(), user.name.as_str()))
+        .collect();
+    let mut taken_names: HashSet<String> =
+        stored_users.iter().map(|u| u.name.to_string()).collect();
+    let mut renames: HashMap<String, String> = HashMap::new();
+    let mut new_users = Vec::new();
+    let mut seen_emails = HashSet::new();
+    for (line, user) in users {
+        if !seen_emails.insert(user.email.to_string()) {
+            continue;
+        }
+        match stored_by_email.get(user.email.as_str()) {
+            Some(stored_name) if *stored_name != user.name => {
+                report
+                    .users_remapped
+                    .push((user.name.to_string(), stored_name.to_string()));
+                renames.insert(user.name, stored_name.to_string());
+            }
+            Some(_) => {}
+            None if !taken_names.insert(user.name.to_string()) => problems.push(format!(
+                "line {}: user name `{}` is taken by a user with another email",
+                line, user.name
+            )),
+            None => {
+                report.users_created.push(user.name.to_string());
+                let mut new_user = WigglesUser {
+                    id: user.id,
+                    name: user.name,
+                    password: user.password,
+                    email: user.email,
+                    public_key: None,
+                    signing_key: None,
+                };
+                // archives from before passwords were hashed hold them in plain text
+                if new_user.has_legacy_password() {
+                    let password = Zeroizing::new(mem::take(&mut new_user.password));
+                    new_user.set_password(&password)?;
+                }
+                new_users.push(new_user);
+            }
+        }
+    }
+    if !problems.is_empty() {
+        return Err(ImportError::Invalid(problems));
+    }
+
+    let rename = |name: &str| renames.get(name).map_or(name, String::as_str).to_string();
+    // authors are linked to their user by email, or by name in archives from before that.
+    // Authors the archive and database don't know get a placeholder user.
+    let known_users = stored_users.iter().chain(&new_users);
+    let mut names_by_email: HashMap<String, String> = known_users
+        .clone()
+        .map(|user| (user.email.to_string(), user.name.to_string()))
+        .collect();
+    let mut emails_by_name: HashMap<String, String> = known_users
+        .map(|user| (user.name.to_string(), user.email.to_string()))
+        .collect();
+    let mut placeholders = Vec::new();
+    let mut seen_ids = HashSet::new();
+    let mut new_messages = Vec::new();
+    for mut message in messages {
+        if stored_ids.contains(&message.id) || !seen_ids.insert(message.id) {
+            report.duplicate_messages += 1;
+            continue;
+        }
+        message.name = rename(&message.name);
+        match names_by_email.get(&message.author_email) {
+            Some(author) => message.name = author.to_string(),
+            None => {
+                let author_email = match emails_by_name.get(&message.name) {
+                    Some(author_email) => author_email.to_string(),
+                    None => {
+                        let placeholder = WigglesUser::placeholder(&message.name);
+                        report.users_created.push(placeholder.name.to_string());
+                        names_by_email
+                            .insert(placeholder.email.to_string(), placeholder.name.to_string());
+                        emails_by_name
+                            .insert(placeholder.name.to_string(), placeholder.email.to_string());
+                        let author_email = placeholder.email.to_string();
+                        placeholders.push(placeholder);
+                        author_email
+                    }
+                };
+                message.author_email = author_email;
+            }
+        }
+        if let Some((first, second)) = rooms::dm_participants(&message.room) {
+            message.room = rooms::dm_room(&rename(first), &rename(second));
+        }
+        new_messages.push(message);
+    }
+    new_users.extend(placeholders);
+    report.messages_imported = new_messages.len();
+    Ok(Plan {
+        report,
+        users: new_users,
+        messages: new_messages,
+    })
+}
+
+// Parses and validates every line, collecting all problems before giving up
+fn read_archive(path: &Path) -> Result<Archive, ImportError> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut users = Vec::new();
+    let mut messages = Vec::new();
+    let mut problems = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line_number = i + 1;
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = match serde_json::from_str::<Record>(&line) {
+            Ok(record) => record,
+            Err(_) => {
+                problems.push(format!(
+                    "line {}: not a message or user record",
+                    line_number
+                ));
+                continue;
+            }
+        };
+        let problem = match &record {
+            Record::Message(message) => validate_message(message),
+            Record::User(user) => validate_user(user),
+        };
+        match (problem, record) {
+            (Some(problem), _) => problems.push(format!("line {}: {}", line_number, problem)),
+            (None, Record::Message(message)) => messages.push(message),
+            (None, Record::User(user)) => users.push((line_number, user)),
+        }
+    }
+    match problems.is_empty() {
+        true => Ok(Archive { users, messages }),
+        false => Err(ImportError::Invalid(problems)),
+    }
+}
+
+fn validate_message(message: &Message) -> Option<String> {
+    if message.name.trim().is_empty() {
+        return Some(format!("message {} has no author", message.id));
+    }
+    if message.body.trim().is_empty() {
+        return Some(format!("message {} has an empty body", message.id));
+    }
+    if !rooms::is_valid_name(&message.room) && rooms::dm_participants(&message.room).is_none() {
+        return Some(format!(
+            "message {} has an invalid room `{}`",
+            message.id, message.room
+        ));
+    }
+    None
+}
+
+fn validate_user(user: &ImportedUser) -> Option<String> {
+    if user.name.trim().is_empty() {
+        return Some(format!("user {} has no name", user.email));
+    }
+    if !rooms::is_valid_user_name(&user.name) {
+        return Some(format!(
+            "user {} has an invalid name `{}`, names can't hold `:` or whitespace",
+            user.email, user.name
+        ));
+    }
+    if !user.email.contains('@') {
+        return Some(format!(
+            "user {} has an invalid email `{}`",
+            user.name, user.email
+        ));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn user(name: &str, email: &str) -> ImportedUser {
+        ImportedUser {
+            id: 1,
+            name: name.to_string(),
+            email: email.to_string(),
+            password: String::new(),
+        }
+    }
+
+    fn message(id: i32, name: &str, room: &str, author_email: &str) -> Message {
+        Message {
+            id,
+            name: name.to_string(),
+            body: "hi".to_string(),
+            published: true,
+            room: room.to_string(),
+            created_at: Utc::now(),
+            author_email: author_email.to_string(),
+        }
+    }
+
+    fn stored(name: &str, email: &str) -> WigglesUser {
+        WigglesUser {
+            name: name.to_string(),
+            email: email.to_string(),
+            ..WigglesUser::default()
+        }
+    }
+
+    // An archive file of its own for each test, removed when dropped
+    struct TempArchive(PathBuf);
+
+    impl TempArchive {
+        fn new(name: &str, contents: &str) -> TempArchive {
+            let path = env::temp_dir().join(format!(
+                "wiggles-test-{}-{}.jsonl",
+                name,
+                std::process::id()
+            ));
+            fs::write(&path, contents).unwrap();
+            TempArchive(path)
+        }
+    }
+
+    impl Drop for TempArchive {
+        fn drop(&mut self) {
+            fs::remove_file(&self.0).ok();
+        }
+    }
+
+    #[test]
+    fn archives_hold_messages_and_users() {
+        let archive = TempArchive::new(
+            "archive",
+            concat!(
+                r#"{"id":1,"name":"alice","email":"alice@example.com"}"#,
+                "\n\n",
+                r#"{"id":7,"name":"alice","body":"hi","published":true}"#,
+                "\n",
+            ),
+        );
+        let Archive { users, messages } = read_archive(&archive.0).unwrap();
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].0, 1);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].room, rooms::DEFAULT_ROOM);
+        assert_eq!(messages[0].author_email, "");
+    }
+
+    #[test]
+    fn every_invalid_line_is_reported() {
+        let archive = TempArchive::new(
+            "invalid",
+            concat!(
+                r#"{"id":7,"name":"alice","body":" ","published":true}"#,
+                "\n",
+                "not json\n",
+                r#"{"id":1,"name":"a:b","email":"ab@example.com"}"#,
+                "\n",
+            ),
+        );
+        match read_archive(&archive.0) {
+            Err(ImportError::Invalid(problems)) => {
+                assert_eq!(problems.len(), 3);
+                assert!(problems[0].starts_with("line 1: message 7 has an empty body"));
+                assert!(problems[1].starts_with("line 2:"));
+                assert!(problems[2].starts_with("line 3: user ab@example.com"));
+            }
+            _ => panic!("the archive is invalid"),
+        }
+    }
+
+    #[test]
+    fn messages_need_an_author_body_and_room() {
+        assert!(validate_message(&message(1, "alice", "general", "")).is_none());
+        assert!(validate_message(&message(1, "alice", "dm:alice:bob", "")).is_none());
+        assert!(validate_message(&message(1, " ", "general", "")).is_some());
+        assert!(validate_message(&message(1, "alice", "two words", "")).is_some());
+        let mut empty = message(1, "alice", "general", "");
+        empty.body = "\n".to_string();
+        assert!(validate_message(&empty).is_some());
+    }
+
+    #[test]
+    fn archive_users_are_renamed_to_the_stored_user_with_their_email() {
+        let archive = Archive {
+            users: vec![
+                (1, user("al", "alice@example.com")),
+                (2, user("carol", "carol@example.com")),
+            ],
+            messages: vec![
+                message(1, "al", "dm:al:bob", ""),
+                message(2, "carol", "general", "carol@example.com"),
+                message(5, "dave", "general", ""),
+                message(4, "al", "general", ""),
+                message(4, "al", "general", ""),
+            ],
+        };
+        let stored_users = [
+            stored("alice", "alice@example.com"),
+            stored("bob", "bob@example.com"),
+        ];
+        let plan = plan(archive, &stored_users, &HashSet::from([4])).unwrap();
+        assert_eq!(
+            plan.report.users_remapped,
+            [("al".to_string(), "alice".to_string())]
+        );
+        assert_eq!(plan.report.users_created, ["carol", "dave"]);
+        assert_eq!(plan.report.duplicate_messages, 2);
+        assert_eq!(plan.report.messages_imported, 3);
+
+        let [dm, carols, daves] = &plan.messages[..] else {
+            panic!("three messages are imported");
+        };
+        assert_eq!(
+            (dm.name.as_str(), dm.room.as_str()),
+            ("alice", "dm:alice:bob")
+        );
+        assert_eq!(dm.author_email, "alice@example.com");
+        assert_eq!(carols.author_email, "carol@example.com");
+        // authors nobody knows get a placeholder user, who can't log in
+        assert_eq!(daves.author_email, "dave@imported.invalid");
+        let names: Vec<&str> = plan.users.iter().map(|u| u.name.as_str()).collect();
+        assert_eq!(names, ["carol", "dave"]);
+    }
+
+    #[test]
+    fn names_taken_by_another_email_are_conflicts() {
+        let archive = Archive {
+            users: vec![(4, user("alice", "other@example.com"))],
+            messages: Vec::new(),
+        };
+        let stored_users = [stored("alice", "alice@example.com")];
+        match plan(archive, &stored_users, &HashSet::new()) {
+            Err(ImportError::Invalid(problems)) => assert!(problems[0].starts_with("line 4:")),
+            _ => panic!("the name is taken"),
+        }
+    }
+
+    #[test]
+    fn plain_text_passwords_are_hashed_on_import() {
+        let mut legacy = user("alice", "alice@example.com");
+        legacy.password = "hunter2".to_string();
+        let mut hashed = stored("bob", "bob@example.com");
+        hashed.set_password("correct horse").unwrap();
+        let mut already_hashed = user("bob", "bob@example.com");
+        already_hashed.password = hashed.password.to_string();
+        let archive = Archive {
+            users: vec![(1, legacy), (2, already_hashed)],
+            messages: Vec::new(),
+        };
+        let plan = plan(archive, &[], &HashSet::new()).unwrap();
+        assert_ne!(plan.users[0].password, "hunter2");
+        assert!(plan.users[0].verify_password("hunter2"));
+        assert!(!plan.users[0].has_legacy_password());
+        assert_eq!(plan.users[1].password, hashed.password);
+    }
+
+    #[test]
+    fn user_names_with_separators_are_rejected() {
+        assert!(validate_user(&user("alice", "alice@example.com")).is_none());
+        assert!(validate_user(&user("a:b", "ab@example.com")).is_some());
+        assert!(validate_user(&user("alice b", "alice@example.com")).is_some());
+        assert!(validate_user(&user(" ", "blank@example.com")).is_some());
+        assert!(validate_user(&user("alice", "no-email")).is_some());
+    }
+}
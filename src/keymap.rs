@@ -0,0 +1,515 @@
+use crate::config::ConfigError;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+
+pub const PRESETS: [&str; 3] = ["default", "vim", "emacs"];
+
+/// `[keymap]` section of the config file
+///
+/// ```toml
+/// [keymap]
+/// preset = "vim"
+///
+/// [keymap.normal]
+/// quit = ["q", "C-c"]
+///
+/// [keymap.insert]
+/// clear_line = "C-u"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct KeymapConfig {
+    /// One of the presets, `default` when unset
+    pub preset: Option<String>,
+    /// Bindings replacing the preset's for normal mode actions
+    pub normal: HashMap<String, KeyList>,
+    /// Bindings replacing the preset's for editing, login and search actions
+    pub insert: HashMap<String, KeyList>,
+}
+
+/// A single key or a list of keys bound to one action
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum KeyList {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    // normal mode
+    Quit,
+    StartEditing,
+    Login,
+    FocusNext,
+    FocusPrevious,
+    SelectNext,
+    SelectPrevious,
+    ToggleMembers,
+    Search,
+    // editing and login
+    Submit,
+    Cancel,
+    DeleteBackward,
+    DeleteWord,
+    ClearLine,
+    CompleteMention,
+    // search
+    NextResult,
+    PreviousResult,
+}
+
+impl Action {
+    const NORMAL: [Action; 9] = [
+        Action::Quit,
+        Action::StartEditing,
+        Action::Login,
+        Action::FocusNext,
+        Action::FocusPrevious,
+        Action::SelectNext,
+        Action::SelectPrevious,
+        Action::ToggleMembers,
+        Action::Search,
+    ];
+    const INSERT: [Action; 8] = [
+        Action::Submit,
+        Action::Cancel,
+        Action::DeleteBackward,
+        Action::DeleteWord,
+        Action::ClearLine,
+        Action::CompleteMention,
+        Action::NextResult,
+        Action::PreviousResult,
+    ];
+
+    /// Name used for the action in the config file
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Quit => "quit",
+            Action::StartEditing => "start_editing",
+            Action::Login => "login",
+            Action::FocusNext => "focus_next",
+            Action::FocusPrevious => "focus_previous",
+            Action::SelectNext => "select_next",
+            Action::SelectPrevious => "select_previous",
+            Action::ToggleMembers => "toggle_members",
+            Action::Search => "search",
+            Action::Submit => "submit",
+            Action::Cancel => "cancel",
+            Action::DeleteBackward => "delete_backward",
+            Action::DeleteWord => "delete_word",
+            Action::ClearLine => "clear_line",
+            Action::CompleteMention => "complete_mention",
+            Action::NextResult => "next_result",
+            Action::PreviousResult => "previous_result",
+        }
+    }
+
+    /// What the action does, phrased for the help bar
+    pub fn description(self) -> &'static str {
+        match self {
+            Action::Quit => "to exit",
+            Action::StartEditing => "to start editing",
+            Action::Login => "to login",
+            Action::FocusNext => "to switch panes",
+            Action::FocusPrevious => "to switch panes back",
+            Action::SelectNext => "for the next room",
+            Action::SelectPrevious => "for the previous room",
+            Action::ToggleMembers => "to show members",
+            Action::Search => "to search messages",
+            Action::Submit => "to record the message",
+            Action::Cancel => "to stop editing",
+            Action::DeleteBackward => "to delete a character",
+            Action::DeleteWord => "to delete a word",
+            Action::ClearLine => "to clear the input",
+            Action::CompleteMention => "to complete an @mention",
+            Action::NextResult => "for the next result",
+            Action::PreviousResult => "for the previous result",
+        }
+    }
+}
+
+/// A key press with its modifiers, written as `q`, `Enter`, `C-u` or `M-Backspace`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Key {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+impl Key {
+    fn new(code: KeyCode) -> Key {
+        Key {
+            code,
+            modifiers: KeyModifiers::NONE,
+        }
+    }
+
+    fn ctrl(c: char) -> Key {
+        Key {
+            code: KeyCode::Char(c),
+            modifiers: KeyModifiers::CONTROL,
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Key> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut rest = value;
+        loop {
+            let (modifier, stripped) = match rest.split_once('-') {
+                Some((prefix, stripped)) if !stripped.is_empty() => (prefix, stripped),
+                _ => break,
+            };
+            modifiers |= match modifier.to_lowercase().as_str() {
+                "c" | "ctrl" => KeyModifiers::CONTROL,
+                "m" | "alt" => KeyModifiers::ALT,
+                "s" | "shift" => KeyModifiers::SHIFT,
+                _ => return None,
+            };
+            rest = stripped;
+        }
+        let code = match rest.to_lowercase().as_str() {
+            "enter" | "return" => KeyCode::Enter,
+            "esc" | "escape" => KeyCode::Esc,
+            "tab" => KeyCode::Tab,
+            "backtab" => KeyCode::BackTab,
+            "backspace" => KeyCode::Backspace,
+            "delete" | "del" => KeyCode::Delete,
+            "space" => KeyCode::Char(' '),
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            _ => {
+                let mut chars = rest.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => KeyCode::Char(c),
+                    _ => return None,
+                }
+            }
+        };
+        Some(Key { code, modifiers })
+    }
+}
+
+impl From<KeyEvent> for Key {
+    fn from(event: KeyEvent) -> Key {
+        // shift is already reflected in the character itself
+        let modifiers = match event.code {
+            KeyCode::Char(_) => event.modifiers - KeyModifiers::SHIFT,
+            _ => event.modifiers,
+        };
+        Key {
+            code: event.code,
+            modifiers,
+        }
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            f.write_str("C-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            f.write_str("M-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            f.write_str("S-")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => f.write_str("Space"),
+            KeyCode::Char(c) => write!(f, "{}", c),
+            KeyCode::F(n) => write!(f, "F{}", n),
+            code => write!(f, "{:?}", code),
+        }
+    }
+}
+
+/// Key bindings for normal mode and for text input (editing, login and search)
+#[derive(Clone, Debug)]
+pub struct Keymap {
+    normal: Vec<(Key, Action)>,
+    insert: Vec<(Key, Action)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        Keymap::preset("default").unwrap()
+    }
+}
+
+impl Keymap {
+    pub fn from_config(config: &KeymapConfig) -> Result<Keymap, ConfigError> {
+        let name = config.preset.as_deref().unwrap_or("default");
+        let mut keymap = Keymap::preset(name).ok_or_else(|| {
+            ConfigError::Invalid(format!(
+                "unknown keymap preset `{}`, expected one of {}",
+                name,
+                PRESETS.join(", ")
+            ))
+        })?;
+        apply_overrides(
+            &mut keymap.normal,
+            &Action::NORMAL,
+            "normal",
+            &config.normal,
+        )?;
+        apply_overrides(
+            &mut keymap.insert,
+            &Action::INSERT,
+            "insert",
+            &config.insert,
+        )?;
+        Ok(keymap)
+    }
+
+    pub fn preset(name: &str) -> Option<Keymap> {
+        use Action::*;
+        let key = |value: &str| Key::parse(value).unwrap();
+        let insert_common = vec![
+            (Key::new(KeyCode::Enter), Submit),
+            (Key::new(KeyCode::Esc), Cancel),
+            (Key::new(KeyCode::Backspace), DeleteBackward),
+            (Key::new(KeyCode::Tab), CompleteMention),
+            (Key::new(KeyCode::Down), NextResult),
+            (Key::new(KeyCode::Up), PreviousResult),
+        ];
+        let normal_common = vec![
+            (Key::new(KeyCode::Tab), FocusNext),
+            (Key::new(KeyCode::BackTab), FocusPrevious),
+            (Key::new(KeyCode::Down), SelectNext),
+            (Key::new(KeyCode::Up), SelectPrevious),
+            (key("m"), ToggleMembers),
+            (key("/"), Search),
+        ];
+        let keymap = match name {
+            "default" => Keymap {
+                normal: [
+                    normal_common,
+                    vec![
+                        (key("q"), Quit),
+                        (key("e"), StartEditing),
+                        (key("l"), Login),
+                    ],
+                ]
+                .concat(),
+                insert: insert_common,
+            },
+            "vim" => Keymap {
+                normal: [
+                    normal_common,
+                    vec![
+                        (key("q"), Quit),
+                        (key("i"), StartEditing),
+                        (key("a"), StartEditing),
+                        (key("L"), Login),
+                        (key("j"), SelectNext),
+                        (key("k"), SelectPrevious),
+                        (Key::ctrl('w'), FocusNext),
+                    ],
+                ]
+                .concat(),
+                insert: [
+                    insert_common,
+                    vec![
+                        (Key::ctrl('['), Cancel),
+                        (Key::ctrl('h'), DeleteBackward),
+                        (Key::ctrl('w'), DeleteWord),
+                        (Key::ctrl('u'), ClearLine),
+                        (Key::ctrl('n'), NextResult),
+                        (Key::ctrl('p'), PreviousResult),
+                    ],
+                ]
+                .concat(),
+            },
+            "emacs" => Keymap {
+                normal: [
+                    normal_common,
+                    vec![
+                        (Key::ctrl('c'), Quit),
+                        (key("q"), Quit),
+                        (key("e"), StartEditing),
+                        (key("l"), Login),
+                        (Key::ctrl('n'), SelectNext),
+                        (Key::ctrl('p'), SelectPrevious),
+                        (key("M-o"), FocusNext),
+                        (Key::ctrl('s'), Search),
+                    ],
+                ]
+                .concat(),
+                insert: [
+                    insert_common,
+                    vec![
+                        (Key::ctrl('g'), Cancel),
+                        (Key::ctrl('m'), Submit),
+                        (Key::ctrl('h'), DeleteBackward),
+                        (key("M-Backspace"), DeleteWord),
+                        (Key::ctrl('u'), ClearLine),
+                        (Key::ctrl('n'), NextResult),
+                        (Key::ctrl('p'), PreviousResult),
+                    ],
+                ]
+                .concat(),
+            },
+            _ => return None,
+        };
+        Some(keymap)
+    }
+
+    /// The normal mode action bound to a key press
+    pub fn normal_action(&self, event: KeyEvent) -> Option<Action> {
+        lookup(&self.normal, event)
+    }
+
+    /// The editing, login and search action bound to a key press
+    pub fn insert_action(&self, event: KeyEvent) -> Option<Action> {
+        lookup(&self.insert, event)
+    }
+
+    /// First key bound to an action, for the help bar
+    pub fn key_for(&self, action: Action) -> Option<Key> {
+        self.normal
+            .iter()
+            .chain(self.insert.iter())
+            .find(|(_, bound)| *bound == action)
+            .map(|(key, _)| *key)
+    }
+
+    /// `(key, description)` pairs for the help bar, in the order given
+    pub fn help(&self, actions: &[Action]) -> Vec<(String, &'static str)> {
+        actions
+            .iter()
+            .filter_map(|action| {
+                self.key_for(*action)
+                    .map(|key| (key.to_string(), action.description()))
+            })
+            .collect()
+    }
+}
+
+fn lookup(bindings: &[(Key, Action)], event: KeyEvent) -> Option<Action> {
+    let key = Key::from(event);
+    bindings
+        .iter()
+        .find(|(bound, _)| *bound == key)
+        .map(|(_, action)| *action)
+}
+
+fn apply_overrides(
+    bindings: &mut Vec<(Key, Action)>,
+    actions: &[Action],
+    section: &str,
+    overrides: &HashMap<String, KeyList>,
+) -> Result<(), ConfigError> {
+    for (name, keys) in overrides {
+        let action = actions
+            .iter()
+            .copied()
+            .find(|action| action.name() == name)
+            .ok_or_else(|| {
+                ConfigError::Invalid(format!("unknown action `keymap.{}.{}`", section, name))
+            })?;
+        let keys = match keys {
+            KeyList::One(key) => std::slice::from_ref(key),
+            KeyList::Many(keys) => keys.as_slice(),
+        };
+        bindings.retain(|(_, bound)| *bound != action);
+        for value in keys {
+            let key = Key::parse(value).ok_or_else(|| {
+                ConfigError::Invalid(format!(
+                    "`keymap.{}.{}` has an unknown key `{}`",
+                    section, name, value
+                ))
+            })?;
+            // a key can only trigger one action
+            bindings.retain(|(bound, _)| *bound != key);
+            bindings.push((key, action));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn parses_keys_with_modifiers() {
+        assert_eq!(Key::parse("q"), Some(Key::new(KeyCode::Char('q'))));
+        assert_eq!(Key::parse("C-w"), Some(Key::ctrl('w')));
+        assert_eq!(
+            Key::parse("M-Backspace"),
+            Some(Key {
+                code: KeyCode::Backspace,
+                modifiers: KeyModifiers::ALT,
+            })
+        );
+        assert_eq!(Key::parse("-"), Some(Key::new(KeyCode::Char('-'))));
+        assert_eq!(Key::parse("X-q"), None);
+        assert_eq!(Key::parse("qq"), None);
+        assert_eq!(Key::parse("C-w").unwrap().to_string(), "C-w");
+    }
+
+    #[test]
+    fn shifted_characters_match_their_binding() {
+        let keymap = Keymap::preset("vim").unwrap();
+        let event = press(KeyCode::Char('L'), KeyModifiers::SHIFT);
+        assert_eq!(keymap.normal_action(event), Some(Action::Login));
+    }
+
+    #[test]
+    fn overrides_replace_preset_bindings() {
+        let config = KeymapConfig {
+            preset: Some("emacs".to_string()),
+            normal: HashMap::from([(
+                "quit".to_string(),
+                KeyList::Many(vec!["x".to_string(), "C-q".to_string()]),
+            )]),
+            insert: HashMap::from([("clear_line".to_string(), KeyList::One("C-k".to_string()))]),
+        };
+        let keymap = Keymap::from_config(&config).unwrap();
+        let quit = |code, modifiers| keymap.normal_action(press(code, modifiers));
+        assert_eq!(
+            quit(KeyCode::Char('x'), KeyModifiers::NONE),
+            Some(Action::Quit)
+        );
+        assert_eq!(
+            quit(KeyCode::Char('q'), KeyModifiers::CONTROL),
+            Some(Action::Quit)
+        );
+        assert_eq!(quit(KeyCode::Char('q'), KeyModifiers::NONE), None);
+        let clear = press(KeyCode::Char('k'), KeyModifiers::CONTROL);
+        assert_eq!(keymap.insert_action(clear), Some(Action::ClearLine));
+        let old_clear = press(KeyCode::Char('u'), KeyModifiers::CONTROL);
+        assert_eq!(keymap.insert_action(old_clear), None);
+    }
+
+    #[test]
+    fn rejects_unknown_presets_actions_and_keys() {
+        let preset = KeymapConfig {
+            preset: Some("nano".to_string()),
+            ..KeymapConfig::default()
+        };
+        assert!(Keymap::from_config(&preset).is_err());
+        let action = KeymapConfig {
+            normal: HashMap::from([("submit".to_string(), KeyList::One("x".to_string()))]),
+            ..KeymapConfig::default()
+        };
+        assert!(Keymap::from_config(&action).is_err());
+        let key = KeymapConfig {
+            insert: HashMap::from([("submit".to_string(), KeyList::One("C-".to_string()))]),
+            ..KeymapConfig::default()
+        };
+        assert!(Keymap::from_config(&key).is_err());
+    }
+}
@@ -0,0 +1,32 @@
+use env_logger::{Builder, Target};
+use log::LevelFilter;
+use std::fs::{self, OpenOptions};
+use std::path::PathBuf;
+
+pub const LEVELS: [&str; 6] = ["off", "error", "warn", "info", "debug", "trace"];
+const LOG_DIR: &str = "wiggles";
+const LOG_FILE: &str = "wiggles.log";
+
+/// Logs to stderr, or to a file while the TUI owns the terminal
+pub fn init(level: LevelFilter, to_file: bool) {
+    let mut builder = Builder::new();
+    builder.filter_level(level);
+    if to_file {
+        match open_log_file() {
+            Some(file) => builder.target(Target::Pipe(Box::new(file))),
+            None => builder.filter_level(LevelFilter::Off),
+        };
+    }
+    builder.init();
+}
+
+/// `$XDG_CACHE_HOME/wiggles/wiggles.log`
+fn log_path() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join(LOG_DIR).join(LOG_FILE))
+}
+
+fn open_log_file() -> Option<fs::File> {
+    let path = log_path()?;
+    fs::create_dir_all(path.parent()?).ok()?;
+    OpenOptions::new().create(true).append(true).open(path).ok()
+}
@@ -14,33 +14,149 @@ mod events {
     pub mod mpsc_channel_handler;
     pub mod producer;
     pub mod utils;
+    pub mod verifier;
+    pub mod wiggles_event;
 }
+mod api;
 mod app_inputs;
+mod cli;
+mod config;
+mod credentials;
+mod e2e;
+mod export;
+mod import;
+mod keymap;
+mod logging;
+mod markdown_handler;
+mod mentions;
+mod notifications;
+mod presence;
+mod profiles;
+mod rooms;
+mod search;
+mod send;
+mod tail;
+mod theme;
 mod ui_render_handler;
 use app_inputs::App;
+use audio_handlers::AudioPlayer;
+use config::{Config, Overrides};
+use credentials::{CredentialStore, SigningKeys};
+use keymap::Keymap;
+use notifications::Notifier;
+use profiles::Profiles;
+use send::SendError;
+use theme::Theme;
 pub mod audio_handlers;
 pub mod db;
 pub mod schema;
-use std::{error::Error, io, thread};
+use log::LevelFilter;
+use std::{error::Error, io, path::PathBuf, thread};
 use tui::{backend::CrosstermBackend, Terminal};
 
 #[macro_use]
 extern crate diesel;
+#[macro_use]
+extern crate diesel_migrations;
+
+fn main() {
+    let matches = cli::app().get_matches();
+    let level = cli::global(&matches, "log-level")
+        .and_then(|level| level.parse().ok())
+        .unwrap_or(LevelFilter::Warn);
+    let config_path = cli::global(&matches, "config").map(PathBuf::from);
+    let command = matches.subcommand_name().unwrap_or("tui");
+    logging::init(level, command == "tui");
+
+    // a .env file in the working directory still works without a config file. Next to one it is
+    // ignored, since its variables, e.g. DATABASE_URL, would override the file's settings.
+    if !Config::file_exists(config_path.as_deref()) {
+        dotenv::dotenv().ok();
+    }
+    let (config, credentials) = match load_config(config_path, cli::overrides(&matches), command) {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
 
-#[actix_web::main]
-async fn actix_runtime() -> std::io::Result<()> {
-    actix_web::HttpServer::new(|| actix_web::App::new().service(route_handler::get_messages))
-        .bind(("127.0.0.1", 8080))?
-        .run()
-        .await
+    let result = match matches.subcommand() {
+        ("serve", Some(_)) => cli::serve(&config.http.bind),
+        ("send", Some(args)) => cli::send(args, &config, credentials.as_ref()).map_err(Into::into),
+        ("tail", Some(args)) => cli::tail(args),
+        ("user", Some(args)) => cli::user(args),
+        ("migrate", Some(_)) => cli::migrate(),
+        ("export", Some(args)) => cli::export(args).map_err(Into::into),
+        ("import", Some(args)) => cli::import(args).map_err(Into::into),
+        ("credentials", Some(args)) => cli::credentials(args, &config),
+        (_, args) => run_tui(
+            config,
+            credentials,
+            args.is_some_and(|args| args.is_present("serve")),
+        ),
+    };
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        let code = err
+            .downcast_ref::<SendError>()
+            .map_or(1, SendError::exit_code);
+        std::process::exit(code);
+    }
 }
 
-#[actix_web::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    //web server
-    thread::spawn(move || {
-        actix_runtime().unwrap();
-    });
+// The config file, then the credential store, then the environment, then the command line.
+// Checked before any command runs so mistakes show up as one clear message.
+// The store is only unlocked for commands that connect to the transport.
+// The TUI creates it up front for "remember me", otherwise on the first direct message.
+fn load_config(
+    path: Option<PathBuf>,
+    overrides: Overrides,
+    command: &str,
+) -> Result<(Config, Option<CredentialStore>), Box<dyn Error>> {
+    let mut config = Config::load(path)?;
+    config.select_profile(overrides.profile.clone())?;
+    let needs_transport = matches!(command, "tui" | "send" | "tail");
+    let credentials = match needs_transport {
+        true => {
+            let remember_me = command == "tui" && config.credentials.remember_me;
+            CredentialStore::unlock(&config.credentials, remember_me)?
+        }
+        false => None,
+    };
+    if let Some(store) = &credentials {
+        store.apply(&mut config);
+    }
+    config.apply_env();
+    config.apply_overrides(&overrides);
+    config.validate(needs_transport)?;
+    db::configure(&config.database.url);
+    if needs_transport {
+        events::utils::configure(&config.transport)?;
+    }
+    Ok((config, credentials))
+}
+
+fn run_tui(
+    config: Config,
+    credentials: Option<CredentialStore>,
+    serve: bool,
+) -> Result<(), Box<dyn Error>> {
+    let theme = Theme::from_config(&config.theme)?;
+    let keymap = Keymap::from_config(&config.keymap)?;
+    let notifier = Notifier::from_config(&config.notifications)?;
+    let audio = AudioPlayer::start(&config.audio);
+    let profiles = Profiles::from_config(&config);
+
+    // login and sending need the server, so it runs here unless it lives elsewhere
+    if serve || config.http.is_local() {
+        let bind = config.http.bind;
+        thread::spawn(move || {
+            if let Err(err) = route_handler::run_server(bind) {
+                log::error!("HTTP server stopped: {}", err);
+            }
+        });
+    }
 
     enable_raw_mode()?;
     let mut stdout = io::stdout();
@@ -48,8 +164,23 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
     let user = models::WigglesUser::default();
+    let signing_keys = SigningKeys::path(&config.credentials)
+        .and_then(|path| SigningKeys::load(&path))
+        .map_err(|err| log::error!("Events will go out unsigned: {}", err))
+        .ok();
     // create app and run it
-    let app = App::default();
+    let app = App {
+        theme,
+        keymap,
+        notifier,
+        audio,
+        profiles,
+        credentials,
+        credentials_path: CredentialStore::path(&config.credentials).ok(),
+        signing_keys,
+        remember_me: config.credentials.remember_me,
+        ..App::default()
+    };
     let res = app_inputs::run_app(&mut terminal, app, user);
 
     disable_raw_mode()?;
@@ -0,0 +1,293 @@
+use crate::mentions;
+use crate::theme::Theme;
+use tui::{
+    style::{Modifier, Style},
+    text::{Span, Spans},
+};
+
+// Supported subset:
+// **bold** / __bold__, *italic* / _italic_, `inline code`,
+// [text](url), @mentions, > block quotes and ``` fenced code blocks.
+const FENCE: &str = "```";
+const QUOTE_PREFIX: &str = "▎ ";
+const CODE_BLOCK_PREFIX: &str = "  ";
+
+/// Renders message bodies with the active theme,
+/// highlighting mentions of the logged in user
+pub struct BodyRenderer<'t> {
+    pub theme: &'t Theme,
+    pub current_user: &'t str,
+}
+
+impl<'t> BodyRenderer<'t> {
+    /// Parses a message body into styled lines
+    pub fn parse_body<'a>(&self, body: &str) -> Vec<Spans<'a>> {
+        let mut lines = Vec::new();
+        let mut in_code_block = false;
+
+        for line in body.lines() {
+            if line.trim_start().starts_with(FENCE) {
+                in_code_block = !in_code_block;
+                continue;
+            }
+            if in_code_block {
+                lines.push(Spans::from(vec![
+                    Span::raw(CODE_BLOCK_PREFIX),
+                    Span::styled(line.to_string(), self.theme.code),
+                ]));
+            } else if let Some(quoted) = line.strip_prefix('>') {
+                let mut spans = vec![Span::styled(QUOTE_PREFIX, self.theme.quote_marker)];
+                spans.append(&mut self.parse_inline(quoted.trim_start(), self.theme.quote));
+                lines.push(Spans::from(spans));
+            } else {
+                lines.push(Spans::from(self.parse_inline(line, Style::default())));
+            }
+        }
+        lines
+    }
+
+    /// Parses inline markdown (emphasis, code, links and mentions) into styled spans
+    pub fn parse_inline<'a>(&self, text: &str, base: Style) -> Vec<Span<'a>> {
+        let mut spans = Vec::new();
+        let mut plain = String::new();
+        let mut rest = text;
+
+        while let Some(c) = rest.chars().next() {
+            let previous = plain.chars().last();
+            match self.parse_token(rest, previous, base) {
+                Some((mut token_spans, consumed)) => {
+                    flush(&mut plain, &mut spans, base);
+                    spans.append(&mut token_spans);
+                    rest = &rest[consumed..];
+                }
+                None => {
+                    plain.push(c);
+                    rest = &rest[c.len_utf8()..];
+                }
+            }
+        }
+        flush(&mut plain, &mut spans, base);
+        spans
+    }
+
+    // Tries to parse a single inline token at the start of `text`,
+    // returning its spans and the number of bytes consumed.
+    fn parse_token<'a>(
+        &self,
+        text: &str,
+        previous: Option<char>,
+        base: Style,
+    ) -> Option<(Vec<Span<'a>>, usize)> {
+        if let Some(inner) = text.strip_prefix('`') {
+            let end = inner.find('`')?;
+            if end == 0 {
+                return None;
+            }
+            let code = Span::styled(inner[..end].to_string(), self.theme.code);
+            return Some((vec![code], end + 2));
+        }
+        if text.starts_with('[') {
+            return self.parse_link(text, base);
+        }
+        if let Some(name) = mentions::parse_mention(text, previous) {
+            let mut style = base.patch(self.theme.mention);
+            if name.eq_ignore_ascii_case(self.current_user) {
+                style = style.add_modifier(Modifier::REVERSED);
+            }
+            let mention = Span::styled(format!("{}{}", mentions::MENTION_PREFIX, name), style);
+            return Some((vec![mention], name.len() + 1));
+        }
+        for delimiter in ["**", "__"] {
+            if let Some((inner, consumed)) = delimited(text, delimiter, previous) {
+                let bold = self.parse_inline(inner, base.add_modifier(Modifier::BOLD));
+                return Some((bold, consumed));
+            }
+        }
+        for delimiter in ["*", "_"] {
+            if let Some((inner, consumed)) = delimited(text, delimiter, previous) {
+                let italic = self.parse_inline(inner, base.add_modifier(Modifier::ITALIC));
+                return Some((italic, consumed));
+            }
+        }
+        None
+    }
+
+    // Matches `[text](url)` at the start of `text`.
+    fn parse_link<'a>(&self, text: &str, base: Style) -> Option<(Vec<Span<'a>>, usize)> {
+        let label_end = text.find("](")?;
+        let label = &text[1..label_end];
+        let url_start = label_end + 2;
+        let url_end = url_start + text[url_start..].find(')')?;
+        let url = &text[url_start..url_end];
+        if label.is_empty() || url.is_empty() || url.contains(char::is_whitespace) {
+            return None;
+        }
+        let mut spans = self.parse_inline(label, base.patch(self.theme.link));
+        spans.push(Span::styled(format!(" <{}>", url), self.theme.link_url));
+        Some((spans, url_end + 1))
+    }
+}
+
+// Matches `<delimiter>inner<delimiter>` at the start of `text`.
+// Underscores only count at word boundaries so snake_case names survive.
+fn delimited<'t>(
+    text: &'t str,
+    delimiter: &str,
+    previous: Option<char>,
+) -> Option<(&'t str, usize)> {
+    let inner = text.strip_prefix(delimiter)?;
+    let is_underscore = delimiter.starts_with('_');
+    if is_underscore && previous.is_some_and(char::is_alphanumeric) {
+        return None;
+    }
+    if inner.starts_with(char::is_whitespace) || inner.starts_with(delimiter) {
+        return None;
+    }
+    // A doubled single delimiter opens a nested bold span and code spans
+    // are literal, so both are skipped whole instead of closing early.
+    let doubled = delimiter.repeat(2);
+    let mut end = 0;
+    while let Some(c) = inner[end..].chars().next() {
+        let rest = &inner[end..];
+        if delimiter.len() == 1 && rest.starts_with(&doubled) {
+            let before = inner[..end].chars().last();
+            end += delimited(rest, &doubled, before).map_or(doubled.len(), |(_, len)| len);
+            continue;
+        }
+        if let Some(code) = rest.strip_prefix('`').and_then(|code| code.find('`')) {
+            end += code + 2;
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix(delimiter) {
+            let opens_word = is_underscore && after.starts_with(char::is_alphanumeric);
+            if end > 0 && !inner[..end].ends_with(char::is_whitespace) && !opens_word {
+                return Some((&inner[..end], end + delimiter.len() * 2));
+            }
+            end += delimiter.len();
+            continue;
+        }
+        end += c.len_utf8();
+    }
+    None
+}
+
+fn flush<'a>(plain: &mut String, spans: &mut Vec<Span<'a>>, style: Style) {
+    if !plain.is_empty() {
+        spans.push(Span::styled(std::mem::take(plain), style));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(text: &str) -> Vec<(String, Style)> {
+        let theme = Theme::default();
+        let renderer = BodyRenderer {
+            theme: &theme,
+            current_user: "alice",
+        };
+        renderer
+            .parse_inline(text, Style::default())
+            .into_iter()
+            .map(|span| (span.content.into_owned(), span.style))
+            .collect()
+    }
+
+    fn plain(text: &str) -> (String, Style) {
+        (text.to_string(), Style::default())
+    }
+
+    fn styled(text: &str, modifier: Modifier) -> (String, Style) {
+        (text.to_string(), Style::default().add_modifier(modifier))
+    }
+
+    #[test]
+    fn parses_bold_and_italic() {
+        assert_eq!(
+            render("a **b** and _c_"),
+            vec![
+                plain("a "),
+                styled("b", Modifier::BOLD),
+                plain(" and "),
+                styled("c", Modifier::ITALIC),
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_bold_does_not_close_italic() {
+        assert_eq!(
+            render("*a **b** c*"),
+            vec![
+                styled("a ", Modifier::ITALIC),
+                styled("b", Modifier::ITALIC | Modifier::BOLD),
+                styled(" c", Modifier::ITALIC),
+            ]
+        );
+    }
+
+    #[test]
+    fn unclosed_or_spaced_delimiters_stay_plain() {
+        assert_eq!(render("2 * 3 * 4"), vec![plain("2 * 3 * 4")]);
+        assert_eq!(render("**open"), vec![plain("**open")]);
+    }
+
+    #[test]
+    fn snake_case_words_stay_plain() {
+        assert_eq!(
+            render("call some_long_name now"),
+            vec![plain("call some_long_name now")]
+        );
+    }
+
+    #[test]
+    fn code_spans_are_literal() {
+        let theme = Theme::default();
+        assert_eq!(
+            render("run `*not italic*` now"),
+            vec![
+                plain("run "),
+                ("*not italic*".to_string(), theme.code),
+                plain(" now"),
+            ]
+        );
+        assert_eq!(render("*a `b*` c*")[1], ("b*".to_string(), theme.code));
+    }
+
+    #[test]
+    fn links_keep_their_url() {
+        let theme = Theme::default();
+        assert_eq!(
+            render("[docs](https://example.com)"),
+            vec![
+                ("docs".to_string(), Style::default().patch(theme.link)),
+                (" <https://example.com>".to_string(), theme.link_url),
+            ]
+        );
+        assert_eq!(render("[a] (b)"), vec![plain("[a] (b)")]);
+    }
+
+    #[test]
+    fn mentions_highlight_the_current_user() {
+        let theme = Theme::default();
+        let mention = Style::default().patch(theme.mention);
+        assert_eq!(
+            render("hi @bob and @Alice."),
+            vec![
+                plain("hi "),
+                ("@bob".to_string(), mention),
+                plain(" and "),
+                (
+                    "@Alice".to_string(),
+                    mention.add_modifier(Modifier::REVERSED)
+                ),
+                plain("."),
+            ]
+        );
+        assert_eq!(
+            render("mail bob@example.com"),
+            vec![plain("mail bob@example.com")]
+        );
+    }
+}
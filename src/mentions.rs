@@ -0,0 +1,142 @@
+pub const MENTION_PREFIX: char = '@';
+
+/// Characters allowed in a mentioned name after the `@`
+pub fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-' || c == '.'
+}
+
+/// Matches a `@name` mention at the start of `text`, returning the name.
+/// `previous` is the character before the `@`, so emails don't count as mentions.
+pub fn parse_mention(text: &str, previous: Option<char>) -> Option<&str> {
+    if previous.is_some_and(is_name_char) {
+        return None;
+    }
+    let rest = text.strip_prefix(MENTION_PREFIX)?;
+    let end = rest.find(|c| !is_name_char(c)).unwrap_or(rest.len());
+    // a trailing dot is punctuation, not part of the name
+    let name = rest[..end].trim_end_matches('.');
+    match name.is_empty() {
+        true => None,
+        false => Some(name),
+    }
+}
+
+/// Lists every name mentioned in a message body
+pub fn mentioned_names(body: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    let mut previous = None;
+    for (i, c) in body.char_indices() {
+        if c == MENTION_PREFIX {
+            if let Some(name) = parse_mention(&body[i..], previous) {
+                names.push(name);
+            }
+        }
+        previous = Some(c);
+    }
+    names
+}
+
+/// Whether a message body mentions `user_name`, ignoring case
+pub fn mentions_user(body: &str, user_name: &str) -> bool {
+    !user_name.is_empty()
+        && mentioned_names(body)
+            .iter()
+            .any(|name| name.eq_ignore_ascii_case(user_name))
+}
+
+/// Tab completion state for the `@name` being typed at the end of the input
+pub struct MentionCompletion {
+    /// What the user typed after the `@` before completing
+    pub prefix: String,
+    /// Index into the matching names of the current suggestion
+    pub index: usize,
+}
+
+/// Completes the `@name` at the end of `input` from `names`.
+/// Repeated calls with the same state cycle through the matches.
+pub fn complete(
+    input: &mut String,
+    names: &[String],
+    completion: &mut Option<MentionCompletion>,
+) -> bool {
+    let word_start = input.rfind(char::is_whitespace).map_or(0, |i| {
+        i + input[i..].chars().next().map_or(1, char::len_utf8)
+    });
+    let word = &input[word_start..];
+    let typed = match word.strip_prefix(MENTION_PREFIX) {
+        Some(typed) => typed.to_string(),
+        None => return false,
+    };
+
+    let state = completion.get_or_insert(MentionCompletion {
+        prefix: typed,
+        index: 0,
+    });
+    let matches: Vec<&String> = names
+        .iter()
+        .filter(|name| {
+            name.to_lowercase()
+                .starts_with(&state.prefix.to_lowercase())
+        })
+        .collect();
+    if matches.is_empty() {
+        *completion = None;
+        return false;
+    }
+    let name = matches[state.index % matches.len()];
+    state.index += 1;
+
+    input.truncate(word_start);
+    input.push(MENTION_PREFIX);
+    input.push_str(name);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_mentions_but_not_emails() {
+        assert_eq!(parse_mention("@bob hi", None), Some("bob"));
+        assert_eq!(parse_mention("@bob.", Some(' ')), Some("bob"));
+        assert_eq!(parse_mention("@first.last", None), Some("first.last"));
+        assert_eq!(parse_mention("@example.com", Some('b')), None);
+        assert_eq!(parse_mention("@ bob", None), None);
+    }
+
+    #[test]
+    fn finds_mentioned_users() {
+        let body = "@alice and @bob-2, not carol@example.com";
+        assert_eq!(mentioned_names(body), vec!["alice", "bob-2"]);
+        assert!(mentions_user(body, "ALICE"));
+        assert!(!mentions_user(body, "carol"));
+        assert!(!mentions_user(body, ""));
+    }
+
+    #[test]
+    fn completion_cycles_through_matches() {
+        let names = vec!["alice".to_string(), "Alfred".to_string(), "bob".to_string()];
+        let mut completion = None;
+        let mut input = "hi @al".to_string();
+        assert!(complete(&mut input, &names, &mut completion));
+        assert_eq!(input, "hi @alice");
+        assert!(complete(&mut input, &names, &mut completion));
+        assert_eq!(input, "hi @Alfred");
+        assert!(complete(&mut input, &names, &mut completion));
+        assert_eq!(input, "hi @alice");
+    }
+
+    #[test]
+    fn completion_needs_a_mention_after_multibyte_space() {
+        let names = vec!["bob".to_string()];
+        let mut completion = None;
+        let mut input = "hi\u{3000}@b".to_string();
+        assert!(complete(&mut input, &names, &mut completion));
+        assert_eq!(input, "hi\u{3000}@bob");
+
+        let mut input = "hi bob".to_string();
+        assert!(!complete(&mut input, &names, &mut None));
+        assert_eq!(input, "hi bob");
+    }
+}
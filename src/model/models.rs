@@ -1,40 +1,239 @@
 // use crate::audio_handlers;
 use crate::db;
 use crate::error_handler::CustomError;
+use crate::mentions;
+use crate::rooms;
 use crate::schema::wiggles_user::dsl::*;
-use crate::schema::{message, wiggles_user};
+use crate::schema::{message, read_receipt, wiggles_session, wiggles_user};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::password_hash::{PasswordHash, SaltString};
+use argon2::{Argon2, PasswordHasher, PasswordVerifier};
+use chrono::{DateTime, Duration, Utc};
+use diesel::dsl::sql;
+use diesel::pg::{Pg, PgConnection};
 use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use diesel::sql_types::{Bool, Float, Text};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
-#[derive(Serialize, Deserialize, AsChangeset, Insertable, Queryable)]
-#[table_name = "message"]
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use zeroize::Zeroizing;
+
+// Rows per insert, well below Postgres' limit on bind parameters per statement
+const INSERT_BATCH_SIZE: usize = 1000;
+// Email of users made up for message authors without one, the same as in the migration linking them
+const PLACEHOLDER_EMAIL_DOMAIN: &str = "@imported.invalid";
+// Random bytes in a session token
+const SESSION_TOKEN_LENGTH: usize = 32;
+/// Stored messages are loaded with their author's current name, see `message_columns`
+#[derive(Serialize, Deserialize, Queryable)]
 pub struct Message {
     pub id: i32,
+    /// Author's name, joined from their `wiggles_user`
     pub name: String,
     pub body: String,
     pub published: bool,
+    /// Room or direct message conversation the message was sent to
+    #[serde(default = "rooms::default_room")]
+    pub room: String,
+    /// When the message was sent
+    #[serde(default = "Utc::now")]
+    pub created_at: DateTime<Utc>,
+    /// The author's `wiggles_user`, set server side from the session. This is who wrote it,
+    /// `name` is only for showing. Empty in archives from before authors were linked.
+    #[serde(default)]
+    pub author_email: String,
+}
+
+// A message as stored, the author's name lives in their `wiggles_user`
+#[derive(Insertable)]
+#[table_name = "message"]
+struct MessageRow<'a> {
+    id: i32,
+    body: &'a str,
+    published: bool,
+    room: &'a str,
+    created_at: DateTime<Utc>,
+    author_email: &'a str,
+}
+
+impl<'a> From<&'a Message> for MessageRow<'a> {
+    fn from(message: &'a Message) -> MessageRow<'a> {
+        MessageRow {
+            id: message.id,
+            body: &message.body,
+            published: message.published,
+            room: &message.room,
+            created_at: message.created_at,
+            author_email: &message.author_email,
+        }
+    }
 }
+
+type MessageColumns = (
+    message::id,
+    wiggles_user::name,
+    message::body,
+    message::published,
+    message::room,
+    message::created_at,
+    message::author_email,
+);
+
+// `Message`'s fields in order, with the author's name from their user
+fn message_columns() -> MessageColumns {
+    (
+        message::id,
+        wiggles_user::name,
+        message::body,
+        message::published,
+        message::room,
+        message::created_at,
+        message::author_email,
+    )
+}
+
 impl Message {
     pub fn get() -> Result<Vec<Message>, CustomError> {
         let conn = db::connection();
-        let get_messages = message::table.load::<Message>(&conn)?;
+        let get_messages = message::table
+            .inner_join(wiggles_user::table)
+            .select(message_columns())
+            .order((message::created_at, message::id))
+            .load::<Message>(&conn)?;
         Ok(get_messages)
     }
-    pub fn insert(&self) -> Result<Message, CustomError> {
+    /// Ids of every stored message
+    pub fn ids() -> Result<HashSet<i32>, CustomError> {
         let conn = db::connection();
-        let message = diesel::insert_into(message::table)
-            .values(self)
-            .get_result(&conn)?;
-        Ok(message)
+        let ids = message::table.select(message::id).load::<i32>(&conn)?;
+        Ok(ids.into_iter().collect())
     }
-    pub fn message_declaration() -> Message {
-        let new_message = Message {
-            id: 12345,
-            name: String::from("new_message"),
-            body: String::from("new_message body"),
-            published: false,
+    /// Messages in the order they were sent, narrowed down by a filter
+    pub fn filtered(filter: &MessageFilter) -> Result<Vec<Message>, CustomError> {
+        let conn = db::connection();
+        let mut query = message::table
+            .inner_join(wiggles_user::table)
+            .select(message_columns())
+            .order((message::created_at, message::id))
+            .into_boxed();
+        if let Some(in_room) = &filter.room {
+            query = query.filter(message::room.eq(in_room));
+        }
+        if let Some(author) = &filter.user {
+            query = query.filter(
+                wiggles_user::name
+                    .eq(author)
+                    .or(message::author_email.eq(author)),
+            );
+        }
+        if let Some(since) = filter.since {
+            query = query.filter(message::created_at.ge(since));
+        }
+        if let Some(until) = filter.until {
+            query = query.filter(message::created_at.lt(until));
+        }
+        if let Some(user) = &filter.visible_to {
+            query = query.filter(visible_to_user(user));
+        }
+        let mut messages = query.load::<Message>(&conn)?;
+        if let Some(user) = &filter.visible_to {
+            // the query only approximates visibility for names with a `:` in them
+            messages.retain(|m| rooms::is_visible(&m.room, user));
+        }
+        Ok(messages)
+    }
+    /// Messages matching a Postgres full-text query, best matches first.
+    /// Uses the same `to_tsvector('english', body)` expression as the GIN index.
+    /// Direct messages between other users than `visible_to` are left out.
+    pub fn search(
+        query: &str,
+        in_room: Option<&str>,
+        visible_to: &str,
+        limit: i64,
+    ) -> Result<Vec<Message>, CustomError> {
+        let conn = db::connection();
+        let matches =
+            sql::<Bool>("to_tsvector('english', body) @@ websearch_to_tsquery('english', ")
+                .bind::<Text, _>(query)
+                .sql(")");
+        let rank =
+            sql::<Float>("ts_rank(to_tsvector('english', body), websearch_to_tsquery('english', ")
+                .bind::<Text, _>(query)
+                .sql(")");
+        let mut search = message::table
+            .inner_join(wiggles_user::table)
+            .select(message_columns())
+            .filter(matches)
+            .filter(visible_to_user(visible_to))
+            .order(rank.desc())
+            .limit(limit)
+            .into_boxed();
+        if let Some(in_room) = in_room {
+            search = search.filter(message::room.eq(in_room));
+        }
+        Ok(search.load::<Message>(&conn)?)
+    }
+    /// Up to `radius` messages either side of one, from the same room.
+    /// Loads only those, before and after it in the `(created_at, id)` order history uses.
+    pub fn context(
+        message_id: i32,
+        in_room: &str,
+        radius: usize,
+    ) -> Result<Vec<Message>, CustomError> {
+        let conn = db::connection();
+        let room_messages = || {
+            message::table
+                .inner_join(wiggles_user::table)
+                .select(message_columns())
+                .filter(message::room.eq(in_room))
         };
-        new_message
+        let target = room_messages()
+            .filter(message::id.eq(message_id))
+            .first::<Message>(&conn)?;
+        let (sent_at, target_id) = (target.created_at, target.id);
+        let mut context = room_messages()
+            .filter(
+                message::created_at.lt(sent_at).or(message::created_at
+                    .eq(sent_at)
+                    .and(message::id.lt(target_id))),
+            )
+            .order((message::created_at.desc(), message::id.desc()))
+            .limit(radius as i64)
+            .load::<Message>(&conn)?;
+        context.reverse();
+        context.push(target);
+        let after = room_messages()
+            .filter(
+                message::created_at.gt(sent_at).or(message::created_at
+                    .eq(sent_at)
+                    .and(message::id.gt(target_id))),
+            )
+            .order((message::created_at, message::id))
+            .limit(radius as i64)
+            .load::<Message>(&conn)?;
+        context.extend(after);
+        Ok(context)
+    }
+    // only `POST /messages` stores messages, as the session's user.
+    // Clients choose the id, so one already taken is a conflict rather than replaced.
+    pub(super) fn insert(&self) -> Result<Message, CustomError> {
+        let conn = db::connection();
+        diesel::insert_into(message::table)
+            .values(MessageRow::from(self))
+            .execute(&conn)
+            .map_err(|err| match err {
+                DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                    CustomError::new(409, format!("There already is a message {}", self.id))
+                }
+                err => err.into(),
+            })?;
+        Ok(self.clone())
+    }
+    /// Whether this message mentions `user_name` with an `@name`
+    pub fn mentions(&self, user_name: &str) -> bool {
+        mentions::mentions_user(&self.body, user_name)
     }
     pub fn clone(&self) -> Message {
         let new_message = Message {
@@ -42,11 +241,86 @@ impl Message {
             name: self.name.to_string(),
             body: self.body.to_string(),
             published: self.published,
+            room: self.room.to_string(),
+            created_at: self.created_at,
+            author_email: self.author_email.to_string(),
         };
         new_message
     }
 }
 
+// Rooms, and the direct messages `user` is part of, as `rooms::is_visible` decides in Rust
+fn visible_to_user<QS>(user: &str) -> Box<dyn BoxableExpression<QS, Pg, SqlType = Bool>>
+where
+    message::room: SelectableExpression<QS>,
+{
+    let [first, second] = rooms::dm_patterns_for(user);
+    Box::new(
+        message::room
+            .not_like(rooms::dm_pattern())
+            .or(message::room.like(first).escape('\\'))
+            .or(message::room.like(second).escape('\\')),
+    )
+}
+
+/// Narrows down the messages `Message::filtered` loads, `None` matches everything
+#[derive(Debug, Default)]
+pub struct MessageFilter {
+    pub room: Option<String>,
+    /// Author, by name or email
+    pub user: Option<String>,
+    /// Earliest send time, inclusive
+    pub since: Option<DateTime<Utc>>,
+    /// Latest send time, exclusive
+    pub until: Option<DateTime<Utc>>,
+    /// Leaves out direct messages this user isn't part of
+    pub visible_to: Option<String>,
+}
+
+impl MessageFilter {
+    /// Whether a message from the transport passes the filter, as `Message::filtered` would
+    pub fn matches(&self, message: &Message) -> bool {
+        self.room.as_ref().is_none_or(|r| *r == message.room)
+            && self
+                .user
+                .as_ref()
+                .is_none_or(|u| *u == message.name || *u == message.author_email)
+            && self.since.is_none_or(|since| message.created_at >= since)
+            && self.until.is_none_or(|until| message.created_at < until)
+            && self
+                .visible_to
+                .as_ref()
+                .is_none_or(|user| rooms::is_visible(&message.room, user))
+    }
+}
+
+/// Last message a user has seen in a room
+#[derive(Serialize, Deserialize, Insertable, Queryable)]
+#[table_name = "read_receipt"]
+pub struct ReadReceipt {
+    pub user_name: String,
+    pub room: String,
+    pub last_read_message_id: i32,
+}
+impl ReadReceipt {
+    pub fn get() -> Result<Vec<ReadReceipt>, CustomError> {
+        let conn = db::connection();
+        let receipts = read_receipt::table.load::<ReadReceipt>(&conn)?;
+        Ok(receipts)
+    }
+    /// Inserts the receipt or moves the user's existing one for the room forward
+    pub fn upsert(&self) -> Result<ReadReceipt, CustomError> {
+        let conn = db::connection();
+        let receipt = diesel::insert_into(read_receipt::table)
+            .values(self)
+            .on_conflict((read_receipt::user_name, read_receipt::room))
+            .do_update()
+            .set(read_receipt::last_read_message_id.eq(self.last_read_message_id))
+            .get_result(&conn)?;
+        Ok(receipt)
+    }
+}
+
 #[derive(Serialize, Deserialize, AsChangeset, Insertable, Queryable)]
 #[table_name = "wiggles_user"]
 pub struct WigglesUser {
@@ -54,6 +328,12 @@ pub struct WigglesUser {
     pub name: String,
     pub password: String,
     pub email: String,
+    /// Base64 X25519 key for end-to-end encrypted direct messages, set on first TUI login
+    #[serde(default)]
+    pub public_key: Option<String>,
+    /// Base64 Ed25519 key the user's published events are signed with
+    #[serde(default)]
+    pub signing_key: Option<String>,
 }
 impl WigglesUser {
     pub fn get() -> Result<Vec<WigglesUser>, CustomError> {
@@ -62,6 +342,123 @@ impl WigglesUser {
         Ok(get_users)
     }
 
+    pub fn insert(&self) -> Result<WigglesUser, CustomError> {
+        let conn = db::connection();
+        let user = diesel::insert_into(wiggles_user::table)
+            .values(self)
+            .get_result(&conn)?;
+        Ok(user)
+    }
+
+    /// Stores an Argon2 hash of the password, never the password itself
+    pub fn set_password(&mut self, plain_password: &str) -> Result<(), CustomError> {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = Argon2::default()
+            .hash_password(plain_password.as_bytes(), &salt)
+            .map_err(|err| CustomError::new(500, format!("Could not hash password: {}", err)))?;
+        self.password = hash.to_string();
+        Ok(())
+    }
+
+    /// Checks a password against the stored hash. Passwords stored in plain text before
+    /// they were hashed still work, until `upgrade_password` replaces them.
+    pub fn verify_password(&self, plain_password: &str) -> bool {
+        match PasswordHash::new(&self.password) {
+            Ok(hash) => Argon2::default()
+                .verify_password(plain_password.as_bytes(), &hash)
+                .is_ok(),
+            // placeholder users have no password at all
+            Err(_) if self.password.is_empty() => false,
+            // digests are compared so the time taken doesn't depend on where they differ
+            Err(_) => {
+                Sha256::digest(self.password.as_bytes())
+                    .iter()
+                    .zip(Sha256::digest(plain_password.as_bytes()))
+                    .fold(0, |diff, (a, b)| diff | (a ^ b))
+                    == 0
+            }
+        }
+    }
+
+    /// Whether the stored password is plain text from before passwords were hashed
+    pub fn has_legacy_password(&self) -> bool {
+        !self.password.is_empty() && PasswordHash::new(&self.password).is_err()
+    }
+
+    /// Replaces a legacy plain text password with its hash, once it was verified at login
+    pub(super) fn upgrade_password(&mut self, plain_password: &str) -> Result<(), CustomError> {
+        self.set_password(plain_password)?;
+        let conn = db::connection();
+        diesel::update(wiggles_user.filter(email.eq(&self.email)))
+            .set(password.eq(&self.password))
+            .execute(&conn)?;
+        Ok(())
+    }
+
+    /// Publishes the user's encryption and/or signing key, replacing any earlier ones.
+    /// Only `PUT /keys` calls this, for the session's own user.
+    pub(super) fn set_keys(
+        &mut self,
+        encryption_key: Option<&str>,
+        signature_key: Option<&str>,
+    ) -> Result<(), CustomError> {
+        let conn = db::connection();
+        diesel::update(wiggles_user.filter(email.eq(&self.email)))
+            .set((
+                encryption_key.map(|key| public_key.eq(key)),
+                signature_key.map(|key| signing_key.eq(key)),
+            ))
+            .execute(&conn)?;
+        if let Some(key) = encryption_key {
+            self.public_key = Some(key.to_string());
+        }
+        if let Some(key) = signature_key {
+            self.signing_key = Some(key.to_string());
+        }
+        Ok(())
+    }
+
+    /// Author of old messages no user is left for. Without a password nobody can log in as them.
+    pub fn placeholder(author: &str) -> WigglesUser {
+        WigglesUser {
+            name: author.to_string(),
+            email: format!("{}{}", author, PLACEHOLDER_EMAIL_DOMAIN),
+            ..WigglesUser::default()
+        }
+    }
+
+    pub fn find_by_name(user_name: &str) -> Result<Option<WigglesUser>, CustomError> {
+        WigglesUser::find_by_name_on(&db::connection(), user_name)
+    }
+
+    /// `find_by_name` on a given database, for consumers of profiles that aren't shown
+    pub fn find_by_name_on(
+        conn: &PgConnection,
+        user_name: &str,
+    ) -> Result<Option<WigglesUser>, CustomError> {
+        let user = wiggles_user
+            .filter(name.eq(user_name))
+            .first::<WigglesUser>(conn)
+            .optional()?;
+        Ok(user)
+    }
+
+    pub fn find_by_email(user_email: &str) -> Result<Option<WigglesUser>, CustomError> {
+        WigglesUser::find_by_email_on(&db::connection(), user_email)
+    }
+
+    /// `find_by_email` on a given database, like `find_by_name_on`
+    pub fn find_by_email_on(
+        conn: &PgConnection,
+        user_email: &str,
+    ) -> Result<Option<WigglesUser>, CustomError> {
+        let user = wiggles_user
+            .filter(email.eq(user_email))
+            .first::<WigglesUser>(conn)
+            .optional()?;
+        Ok(user)
+    }
+
     pub fn get_by_email(user_email: String) -> Result<Vec<WigglesUser>, CustomError> {
         let conn = db::connection();
         let results = wiggles_user
@@ -80,6 +477,135 @@ impl Default for WigglesUser {
             name: String::new(),
             password: String::new(),
             email: String::new(),
+            public_key: None,
+            signing_key: None,
+        }
+    }
+}
+
+/// A "remember me" login. The client keeps the token, the database only its hash.
+#[derive(Insertable, Queryable)]
+#[table_name = "wiggles_session"]
+pub struct Session {
+    pub token_hash: String,
+    pub user_email: String,
+    pub expires_at: DateTime<Utc>,
+}
+impl Session {
+    /// Starts a session for the user, returning the token to hand to the client
+    pub fn create(
+        user: &WigglesUser,
+        lifetime: Duration,
+    ) -> Result<Zeroizing<String>, CustomError> {
+        let mut bytes = Zeroizing::new([0u8; SESSION_TOKEN_LENGTH]);
+        OsRng.fill_bytes(bytes.as_mut());
+        let token = Zeroizing::new(base64::encode_config(
+            bytes.as_ref(),
+            base64::URL_SAFE_NO_PAD,
+        ));
+        let session = Session {
+            token_hash: session_token_hash(&token),
+            user_email: user.email.to_string(),
+            expires_at: Utc::now() + lifetime,
+        };
+        let conn = db::connection();
+        diesel::insert_into(wiggles_session::table)
+            .values(&session)
+            .execute(&conn)?;
+        Ok(token)
+    }
+
+    /// The user a token was issued to, unless it expired or was revoked
+    pub fn user(token: &str) -> Result<Option<WigglesUser>, CustomError> {
+        let conn = db::connection();
+        let user = wiggles_session::table
+            .inner_join(wiggles_user::table)
+            .filter(wiggles_session::token_hash.eq(session_token_hash(token)))
+            .filter(wiggles_session::expires_at.gt(Utc::now()))
+            .select(wiggles_user::all_columns)
+            .first::<WigglesUser>(&conn)
+            .optional()?;
+        Ok(user)
+    }
+
+    pub fn revoke(token: &str) -> Result<(), CustomError> {
+        let conn = db::connection();
+        diesel::delete(
+            wiggles_session::table
+                .filter(wiggles_session::token_hash.eq(session_token_hash(token))),
+        )
+        .execute(&conn)?;
+        Ok(())
+    }
+}
+
+// Tokens are random, so a fast unsalted hash is enough to keep a database leak from logging anyone in
+fn session_token_hash(token: &str) -> String {
+    base64::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Inserts imported users and messages in one transaction, nothing is written if any insert fails
+pub fn import_history(users: &[WigglesUser], messages: &[Message]) -> Result<(), CustomError> {
+    let conn = db::connection();
+    conn.transaction::<_, DieselError, _>(|| {
+        for batch in users.chunks(INSERT_BATCH_SIZE) {
+            diesel::insert_into(wiggles_user::table)
+                .values(batch)
+                .execute(&conn)?;
+        }
+        for batch in messages.chunks(INSERT_BATCH_SIZE) {
+            let rows: Vec<MessageRow> = batch.iter().map(MessageRow::from).collect();
+            diesel::insert_into(message::table)
+                .values(&rows)
+                .execute(&conn)?;
+        }
+        Ok(())
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user_with_password(stored: &str) -> WigglesUser {
+        WigglesUser {
+            name: "alice".to_string(),
+            email: "alice@example.com".to_string(),
+            password: stored.to_string(),
+            ..WigglesUser::default()
         }
     }
+
+    #[test]
+    fn hashed_passwords_verify() {
+        let mut user = user_with_password("");
+        user.set_password("hunter2").unwrap();
+        assert!(user.password.starts_with("$argon2"));
+        assert!(!user.has_legacy_password());
+        assert!(user.verify_password("hunter2"));
+        assert!(!user.verify_password("hunter3"));
+    }
+
+    #[test]
+    fn legacy_plain_text_passwords_verify_until_upgraded() {
+        let mut user = user_with_password("hunter2");
+        assert!(user.has_legacy_password());
+        assert!(user.verify_password("hunter2"));
+        assert!(!user.verify_password("hunter"));
+        assert!(!user.verify_password(""));
+        // what `upgrade_password` stores
+        user.set_password("hunter2").unwrap();
+        assert!(!user.has_legacy_password());
+        assert!(user.verify_password("hunter2"));
+        assert!(!user.verify_password(&user.password.to_string()));
+    }
+
+    #[test]
+    fn placeholder_users_cant_log_in() {
+        let user = WigglesUser::placeholder("ghost");
+        assert_eq!(user.email, "ghost@imported.invalid");
+        assert!(!user.has_legacy_password());
+        assert!(!user.verify_password(""));
+    }
 }
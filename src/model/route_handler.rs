@@ -1,9 +1,270 @@
+use crate::api::{KeyUpdate, LoginRequest, LoginResponse, NewMessage, ReadUpdate};
+use crate::e2e;
 use crate::error_handler::CustomError;
-use crate::model::models::Message;
-use actix_web::{get, HttpResponse};
+use crate::export::{self, ExportFormat};
+use crate::model::models::{Message, MessageFilter, ReadReceipt, Session, WigglesUser};
+use crate::rooms;
+use actix_web::http::header;
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse};
+use chrono::{Duration, Utc};
+use rand::Rng;
+use serde::Deserialize;
 
+const SEARCH_LIMIT: i64 = 50;
+/// How long "remember me" sessions last, other ones end after a day
+const REMEMBERED_SESSION_DAYS: i64 = 30;
+
+/// Serves the HTTP API until the process is stopped
+#[actix_web::main]
+pub async fn run_server(bind: String) -> std::io::Result<()> {
+    actix_web::HttpServer::new(|| {
+        actix_web::App::new()
+            .service(get_messages)
+            .service(search_messages)
+            .service(export_messages)
+            .service(create_session)
+            .service(delete_session)
+            .service(create_message)
+            .service(update_read_receipt)
+            .service(update_keys)
+    })
+    .bind(bind)?
+    .run()
+    .await
+}
+const MAX_SEARCH_LIMIT: i64 = 500;
+
+/// `GET /user/{name}`, the messages `name` wrote in rooms the session's user can see
 #[get("/user/{name}")]
-pub async fn get_messages() -> Result<HttpResponse, CustomError> {
-    let user = Message::get()?;
-    Ok(HttpResponse::Ok().json(user))
+pub async fn get_messages(
+    request: HttpRequest,
+    name: web::Path<String>,
+) -> Result<HttpResponse, CustomError> {
+    let user = authenticated(&request)?;
+    let messages = Message::filtered(&MessageFilter {
+        user: Some(name.into_inner()),
+        visible_to: Some(user.name),
+        ..MessageFilter::default()
+    })?;
+    Ok(HttpResponse::Ok().json(messages))
+}
+
+#[derive(Deserialize)]
+pub struct SearchParams {
+    q: String,
+    room: Option<String>,
+    limit: Option<i64>,
+}
+
+/// `GET /messages/search?q=<query>[&room=<room>][&limit=<n>]`, best matches first.
+/// Only finds messages in rooms the session's user can see.
+#[get("/messages/search")]
+pub async fn search_messages(
+    request: HttpRequest,
+    params: web::Query<SearchParams>,
+) -> Result<HttpResponse, CustomError> {
+    let user = authenticated(&request)?;
+    let query = params.q.trim();
+    if query.is_empty() {
+        return Err(CustomError::new(
+            400,
+            "The search query is empty".to_string(),
+        ));
+    }
+    let limit = params
+        .limit
+        .unwrap_or(SEARCH_LIMIT)
+        .clamp(1, MAX_SEARCH_LIMIT);
+    let mut messages = Message::search(query, params.room.as_deref(), &user.name, limit)?;
+    // the query only approximates visibility for names with a `:` in them
+    messages.retain(|m| rooms::is_visible(&m.room, &user.name));
+    Ok(HttpResponse::Ok().json(messages))
+}
+
+#[derive(Deserialize)]
+pub struct ExportParams {
+    format: Option<String>,
+    room: Option<String>,
+    user: Option<String>,
+    since: Option<String>,
+    until: Option<String>,
+}
+
+/// `GET /messages/export?format=jsonl|csv|transcript`, filtered by `room`, `user`, `since` and `until`
+/// Only exports rooms the session's user can see.
+#[get("/messages/export")]
+pub async fn export_messages(
+    request: HttpRequest,
+    params: web::Query<ExportParams>,
+) -> Result<HttpResponse, CustomError> {
+    let user = authenticated(&request)?;
+    let bad_request = |err: export::ExportError| CustomError::new(400, err.to_string());
+    let format: ExportFormat = params
+        .format
+        .as_deref()
+        .unwrap_or("jsonl")
+        .parse()
+        .map_err(bad_request)?;
+    let filter = MessageFilter {
+        room: params.room.clone(),
+        user: params.user.clone(),
+        since: params
+            .since
+            .as_deref()
+            .map(export::parse_since)
+            .transpose()
+            .map_err(bad_request)?,
+        until: params
+            .until
+            .as_deref()
+            .map(export::parse_until)
+            .transpose()
+            .map_err(bad_request)?,
+        visible_to: Some(user.name),
+    };
+    let messages = Message::filtered(&filter)?;
+    let mut body = Vec::new();
+    export::write_messages(&messages, format, &mut body)
+        .map_err(|err| CustomError::new(500, err.to_string()))?;
+    let disposition = format!(
+        "attachment; filename=\"wiggles-export.{}\"",
+        format.extension()
+    );
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(("Content-Disposition", disposition))
+        .body(body))
+}
+
+/// `POST /sessions` with a user name or email and password, answering with the session token
+#[post("/sessions")]
+pub async fn create_session(
+    login: web::Json<LoginRequest<'static>>,
+) -> Result<HttpResponse, CustomError> {
+    let user = match WigglesUser::find_by_email(&login.user)? {
+        Some(user) => Some(user),
+        None => WigglesUser::find_by_name(&login.user)?,
+    };
+    let mut user = user
+        .filter(|user| user.verify_password(&login.password))
+        .ok_or_else(|| CustomError::new(401, "Wrong user name or password".to_string()))?;
+    if user.has_legacy_password() {
+        user.upgrade_password(&login.password)?;
+    }
+    let lifetime = match login.remember {
+        true => Duration::days(REMEMBERED_SESSION_DAYS),
+        false => Duration::days(1),
+    };
+    let token = Session::create(&user, lifetime)?;
+    Ok(HttpResponse::Created().json(LoginResponse {
+        token: token.to_string(),
+        name: user.name,
+        email: user.email,
+    }))
+}
+
+/// `DELETE /sessions/current` revokes the token it is called with
+#[delete("/sessions/current")]
+pub async fn delete_session(request: HttpRequest) -> Result<HttpResponse, CustomError> {
+    authenticated(&request)?;
+    Session::revoke(bearer_token(&request)?)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// `POST /messages` stores a message written by the session's user, whatever name the client uses
+#[post("/messages")]
+pub async fn create_message(
+    request: HttpRequest,
+    new_message: web::Json<NewMessage>,
+) -> Result<HttpResponse, CustomError> {
+    let user = authenticated(&request)?;
+    let new_message = new_message.into_inner();
+    if new_message.body.trim().is_empty() {
+        return Err(CustomError::new(400, "The message is empty".to_string()));
+    }
+    // a direct message room has to be one the author is part of
+    let room_allowed = match rooms::dm_participants(&new_message.room) {
+        Some(_) => rooms::is_visible(&new_message.room, &user.name),
+        None => rooms::is_valid_name(&new_message.room),
+    };
+    if !room_allowed {
+        return Err(CustomError::new(
+            400,
+            format!("Can't send to the room `{}`", new_message.room),
+        ));
+    }
+    let message = Message {
+        id: new_message.id.unwrap_or_else(|| rand::thread_rng().gen()),
+        name: user.name,
+        body: new_message.body,
+        published: true,
+        room: new_message.room,
+        created_at: Utc::now(),
+        author_email: user.email,
+    };
+    Ok(HttpResponse::Created().json(message.insert()?))
+}
+
+/// `PUT /read-receipts` moves the session's user's receipt for a room forward
+#[put("/read-receipts")]
+pub async fn update_read_receipt(
+    request: HttpRequest,
+    update: web::Json<ReadUpdate>,
+) -> Result<HttpResponse, CustomError> {
+    let user = authenticated(&request)?;
+    let update = update.into_inner();
+    if !rooms::is_visible(&update.room, &user.name) {
+        return Err(CustomError::new(
+            400,
+            format!("Can't mark the room `{}` read", update.room),
+        ));
+    }
+    let receipt = ReadReceipt {
+        user_name: user.name,
+        room: update.room,
+        last_read_message_id: update.message_id,
+    };
+    Ok(HttpResponse::Ok().json(receipt.upsert()?))
+}
+
+/// `PUT /keys` publishes the session's user's encryption and signing keys
+#[put("/keys")]
+pub async fn update_keys(
+    request: HttpRequest,
+    keys: web::Json<KeyUpdate>,
+) -> Result<HttpResponse, CustomError> {
+    let mut user = authenticated(&request)?;
+    let public_key = keys.public_key.as_deref();
+    let signing_key = keys.signing_key.as_deref();
+    let valid = public_key.is_none_or(e2e::is_public_key)
+        && signing_key.is_none_or(|key| e2e::parse_signing_key(key).is_some());
+    if !valid || (public_key.is_none() && signing_key.is_none()) {
+        return Err(CustomError::new(400, "The keys are invalid".to_string()));
+    }
+    user.set_keys(public_key, signing_key)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// The user of the session whose token is in the `Authorization: Bearer <token>` header
+fn authenticated(request: &HttpRequest) -> Result<WigglesUser, CustomError> {
+    Session::user(bearer_token(request)?)?.ok_or_else(|| {
+        CustomError::new(
+            401,
+            "The session has expired or was revoked, log in again".to_string(),
+        )
+    })
+}
+
+fn bearer_token(request: &HttpRequest) -> Result<&str, CustomError> {
+    request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(|| {
+            CustomError::new(
+                401,
+                "Log in first, the session token goes in the Authorization header".to_string(),
+            )
+        })
 }
@@ -0,0 +1,310 @@
+use crate::audio_handlers::{AudioPlayer, Sound};
+use crate::config::ConfigError;
+use crate::model::models::Message;
+use crate::rooms;
+use chrono::{Local, NaiveTime};
+use log::warn;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::io::{self, Write};
+
+const TITLE: &str = "wiggles";
+const PREVIEW_LENGTH: usize = 120;
+const TIME_FORMAT: &str = "%H:%M";
+
+/// `[notifications]` section of the config file
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct NotificationConfig {
+    /// Ring the terminal bell
+    pub bell: bool,
+    /// Escape sequence asking the terminal for a desktop notification
+    pub terminal: TerminalNotification,
+    /// Play the sound configured for the kind of message
+    pub sound: bool,
+    /// Only notify for messages mentioning the logged in user
+    pub mentions_only: bool,
+    /// Only notify for direct messages
+    pub dms_only: bool,
+    /// Rooms that never notify, `/mute` and `/unmute` change this at runtime
+    pub muted_rooms: Vec<String>,
+    /// Times of day when nothing notifies
+    pub do_not_disturb: Option<QuietHoursConfig>,
+}
+
+impl Default for NotificationConfig {
+    fn default() -> NotificationConfig {
+        NotificationConfig {
+            bell: true,
+            terminal: TerminalNotification::None,
+            sound: false,
+            mentions_only: false,
+            dms_only: false,
+            muted_rooms: Vec::new(),
+            do_not_disturb: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TerminalNotification {
+    None,
+    /// `OSC 9`, understood by iTerm2, kitty, WezTerm and Windows Terminal
+    Osc9,
+    /// `OSC 777`, understood by urxvt and VTE based terminals
+    Osc777,
+}
+
+/// Start and end as `HH:MM`, the range may wrap past midnight
+#[derive(Debug, Deserialize)]
+pub struct QuietHoursConfig {
+    pub start: String,
+    pub end: String,
+}
+
+struct QuietHours {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl QuietHours {
+    fn contains(&self, time: NaiveTime) -> bool {
+        match self.start <= self.end {
+            true => self.start <= time && time < self.end,
+            false => time >= self.start || time < self.end,
+        }
+    }
+}
+
+/// Decides which incoming messages notify and how
+pub struct Notifier {
+    bell: bool,
+    terminal: TerminalNotification,
+    sound: bool,
+    mentions_only: bool,
+    dms_only: bool,
+    muted_rooms: HashSet<String>,
+    quiet_hours: Option<QuietHours>,
+}
+
+impl Default for Notifier {
+    fn default() -> Notifier {
+        Notifier::from_config(&NotificationConfig::default()).unwrap()
+    }
+}
+
+impl Notifier {
+    pub fn from_config(config: &NotificationConfig) -> Result<Notifier, ConfigError> {
+        let quiet_hours = match &config.do_not_disturb {
+            Some(hours) => Some(QuietHours {
+                start: parse_time(&hours.start)?,
+                end: parse_time(&hours.end)?,
+            }),
+            None => None,
+        };
+        Ok(Notifier {
+            bell: config.bell,
+            terminal: config.terminal,
+            sound: config.sound,
+            mentions_only: config.mentions_only,
+            dms_only: config.dms_only,
+            muted_rooms: config.muted_rooms.iter().cloned().collect(),
+            quiet_hours,
+        })
+    }
+
+    pub fn is_muted(&self, room: &str) -> bool {
+        self.muted_rooms.contains(room)
+    }
+
+    pub fn set_muted(&mut self, room: &str, muted: bool) {
+        match muted {
+            true => self.muted_rooms.insert(room.to_string()),
+            false => self.muted_rooms.remove(room),
+        };
+    }
+
+    /// Whether a message from another user should notify right now.
+    /// Direct messages between other users never do.
+    /// With both `mentions_only` and `dms_only` set, either is enough.
+    pub fn should_notify(&self, message: &Message, current_user: &str, now: NaiveTime) -> bool {
+        if current_user.is_empty()
+            || message.name == current_user
+            || !rooms::is_visible(&message.room, current_user)
+            || self.is_muted(&message.room)
+        {
+            return false;
+        }
+        if self.quiet_hours.as_ref().is_some_and(|q| q.contains(now)) {
+            return false;
+        }
+        let is_mention = message.mentions(current_user);
+        let is_dm = rooms::is_dm(&message.room);
+        match (self.mentions_only, self.dms_only) {
+            (false, false) => true,
+            (true, false) => is_mention,
+            (false, true) => is_dm,
+            (true, true) => is_mention || is_dm,
+        }
+    }
+
+    pub fn notify(&self, message: &Message, current_user: &str, audio: &AudioPlayer) {
+        if !self.should_notify(message, current_user, Local::now().time()) {
+            return;
+        }
+        let mut sequence = String::new();
+        if self.bell {
+            sequence.push('\x07');
+        }
+        let summary = format!(
+            "{} in {}",
+            sanitize(&message.name),
+            sanitize(&rooms::display_name(&message.room, current_user))
+        );
+        let body = preview(&message.body);
+        match self.terminal {
+            TerminalNotification::None => {}
+            TerminalNotification::Osc9 => {
+                sequence.push_str(&format!("\x1b]9;{}: {}\x07", summary, body))
+            }
+            TerminalNotification::Osc777 => sequence.push_str(&format!(
+                "\x1b]777;notify;{}: {};{}\x07",
+                TITLE,
+                summary.replace(';', ","),
+                body.replace(';', ",")
+            )),
+        }
+        if !sequence.is_empty() {
+            let mut stdout = io::stdout();
+            if let Err(err) = stdout
+                .write_all(sequence.as_bytes())
+                .and_then(|_| stdout.flush())
+            {
+                warn!("Failed to write notification: {}", err);
+            }
+        }
+        if self.sound {
+            audio.play(sound_for(message, current_user));
+        }
+    }
+}
+
+fn sound_for(message: &Message, current_user: &str) -> Sound {
+    if rooms::is_dm(&message.room) {
+        Sound::DirectMessage
+    } else if message.mentions(current_user) {
+        Sound::Mention
+    } else {
+        Sound::Message
+    }
+}
+
+fn parse_time(value: &str) -> Result<NaiveTime, ConfigError> {
+    NaiveTime::parse_from_str(value, TIME_FORMAT).map_err(|_| {
+        ConfigError::Invalid(format!(
+            "invalid do_not_disturb time `{}`, expected HH:MM",
+            value
+        ))
+    })
+}
+
+// Message text goes inside an escape sequence, so control characters must not reach the terminal
+fn sanitize(text: &str) -> String {
+    text.chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect()
+}
+
+fn preview(body: &str) -> String {
+    let body = sanitize(body);
+    match body.char_indices().nth(PREVIEW_LENGTH) {
+        Some((end, _)) => format!("{}…", &body[..end]),
+        None => body,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn message(name: &str, room: &str, body: &str) -> Message {
+        Message {
+            id: 1,
+            name: name.to_string(),
+            body: body.to_string(),
+            published: true,
+            room: room.to_string(),
+            created_at: Utc::now(),
+            author_email: format!("{}@example.com", name),
+        }
+    }
+
+    fn at(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    fn noon() -> NaiveTime {
+        at(12, 0)
+    }
+
+    #[test]
+    fn skips_own_muted_and_other_peoples_direct_messages() {
+        let mut notifier = Notifier::default();
+        let dm = rooms::dm_room("alice", "bob");
+        assert!(notifier.should_notify(&message("bob", "general", "hi"), "alice", noon()));
+        assert!(notifier.should_notify(&message("bob", &dm, "hi"), "alice", noon()));
+        assert!(!notifier.should_notify(&message("bob", &dm, "hi"), "carol", noon()));
+        assert!(!notifier.should_notify(&message("alice", "general", "hi"), "alice", noon()));
+        assert!(!notifier.should_notify(&message("bob", "general", "hi"), "", noon()));
+        notifier.set_muted("general", true);
+        assert!(!notifier.should_notify(&message("bob", "general", "hi"), "alice", noon()));
+    }
+
+    #[test]
+    fn filters_by_mentions_and_direct_messages() {
+        let config = NotificationConfig {
+            mentions_only: true,
+            dms_only: true,
+            ..NotificationConfig::default()
+        };
+        let notifier = Notifier::from_config(&config).unwrap();
+        let dm = rooms::dm_room("alice", "bob");
+        assert!(notifier.should_notify(&message("bob", "general", "@alice look"), "alice", noon()));
+        assert!(notifier.should_notify(&message("bob", &dm, "hi"), "alice", noon()));
+        assert!(!notifier.should_notify(&message("bob", "general", "hi"), "alice", noon()));
+    }
+
+    #[test]
+    fn quiet_hours_wrap_past_midnight() {
+        let config = NotificationConfig {
+            do_not_disturb: Some(QuietHoursConfig {
+                start: "22:00".to_string(),
+                end: "07:30".to_string(),
+            }),
+            ..NotificationConfig::default()
+        };
+        let notifier = Notifier::from_config(&config).unwrap();
+        let hi = message("bob", "general", "hi");
+        assert!(!notifier.should_notify(&hi, "alice", at(23, 0)));
+        assert!(!notifier.should_notify(&hi, "alice", at(7, 0)));
+        assert!(notifier.should_notify(&hi, "alice", noon()));
+
+        let invalid = NotificationConfig {
+            do_not_disturb: Some(QuietHoursConfig {
+                start: "10pm".to_string(),
+                end: "07:30".to_string(),
+            }),
+            ..NotificationConfig::default()
+        };
+        assert!(Notifier::from_config(&invalid).is_err());
+    }
+
+    #[test]
+    fn previews_are_sanitized_and_shortened() {
+        assert_eq!(preview("a\x1b]9;b\nc"), "a ]9;b c");
+        let long = "x".repeat(PREVIEW_LENGTH + 5);
+        assert_eq!(preview(&long).chars().count(), PREVIEW_LENGTH + 1);
+    }
+}
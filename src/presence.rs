@@ -0,0 +1,239 @@
+use crate::events::wiggles_event::{PresenceEvent, PresenceStatus, TypingEvent};
+use chrono::Utc;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How often each client publishes its presence
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+/// Users go away after this long without pressing a key
+pub const AWAY_AFTER: Duration = Duration::from_secs(5 * 60);
+// a few missed heartbeats and the user is considered gone
+const PRESENCE_TIMEOUT: Duration = Duration::from_secs(45);
+/// Minimum time between typing events from one client
+pub const TYPING_THROTTLE: Duration = Duration::from_secs(3);
+// long enough to bridge the gap between throttled typing events
+const TYPING_EXPIRY: Duration = Duration::from_secs(6);
+
+/// Last known status of every user heard from over the transport,
+/// with when it was sent in milliseconds since the unix epoch
+#[derive(Default)]
+pub struct PresenceMap {
+    last_seen: HashMap<String, (PresenceStatus, i64)>,
+}
+
+impl PresenceMap {
+    /// Records a status as of when it was sent. Events replayed from the transport that are
+    /// older than the timeout, or than the status already known, are dropped.
+    pub fn update(&mut self, event: &PresenceEvent) {
+        self.update_at(event, now_millis());
+    }
+
+    fn update_at(&mut self, event: &PresenceEvent, now: i64) {
+        // a clock running ahead can't keep a user online
+        let sent_at = event.sent_at.min(now);
+        if is_expired(sent_at, now, PRESENCE_TIMEOUT) {
+            return;
+        }
+        if let Some((_, seen)) = self.last_seen.get(&event.name) {
+            if *seen > sent_at {
+                return;
+            }
+        }
+        self.last_seen
+            .insert(event.name.to_string(), (event.status, sent_at));
+    }
+
+    /// A user's status, offline once their heartbeats stop arriving
+    pub fn status(&self, name: &str) -> PresenceStatus {
+        self.status_at(name, now_millis())
+    }
+
+    fn status_at(&self, name: &str, now: i64) -> PresenceStatus {
+        match self.last_seen.get(name) {
+            Some((status, seen)) if !is_expired(*seen, now, PRESENCE_TIMEOUT) => *status,
+            _ => PresenceStatus::Offline,
+        }
+    }
+}
+
+/// Who is typing in which room, forgotten shortly after their typing events stop
+#[derive(Default)]
+pub struct TypingIndicators {
+    // when the last typing event was sent, in milliseconds since the unix epoch
+    typing: HashMap<(String, String), i64>,
+}
+
+impl TypingIndicators {
+    /// Records a typing event as of when it was sent, dropping ones that have expired already
+    pub fn update(&mut self, event: &TypingEvent) {
+        self.update_at(event, now_millis());
+    }
+
+    fn update_at(&mut self, event: &TypingEvent, now: i64) {
+        let sent_at = event.sent_at.min(now);
+        if is_expired(sent_at, now, TYPING_EXPIRY) {
+            return;
+        }
+        let key = (event.room.to_string(), event.name.to_string());
+        let seen = self.typing.entry(key).or_insert(sent_at);
+        *seen = (*seen).max(sent_at);
+    }
+
+    /// Called when a user's message arrives, since they've stopped typing it
+    pub fn stopped(&mut self, room: &str, name: &str) {
+        self.typing.remove(&(room.to_string(), name.to_string()));
+    }
+
+    /// Users typing in a room, except `current_user`
+    pub fn typing_in(&self, room: &str, current_user: &str) -> Vec<&str> {
+        self.typing_in_at(room, current_user, now_millis())
+    }
+
+    fn typing_in_at(&self, room: &str, current_user: &str, now: i64) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .typing
+            .iter()
+            .filter(|((typing_room, name), seen)| {
+                typing_room == room
+                    && name != current_user
+                    && !is_expired(**seen, now, TYPING_EXPIRY)
+            })
+            .map(|((_, name), _)| name.as_str())
+            .collect();
+        names.sort_unstable();
+        names
+    }
+}
+
+/// Whether a typing event is due, at most one per `TYPING_THROTTLE`
+pub fn typing_due(last_sent: Option<Instant>) -> bool {
+    last_sent.is_none_or(|sent| sent.elapsed() >= TYPING_THROTTLE)
+}
+
+fn now_millis() -> i64 {
+    Utc::now().timestamp_millis()
+}
+
+// Whether something sent at `sent_at` is older than `lifetime` at `now`, all in milliseconds
+fn is_expired(sent_at: i64, now: i64, lifetime: Duration) -> bool {
+    now - sent_at >= lifetime.as_millis() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000_000;
+
+    fn presence(name: &str, status: PresenceStatus, sent_at: i64) -> PresenceEvent {
+        PresenceEvent {
+            name: name.to_string(),
+            status,
+            sent_at,
+        }
+    }
+
+    fn ago(duration: Duration) -> i64 {
+        NOW - duration.as_millis() as i64
+    }
+
+    #[test]
+    fn users_go_offline_when_heartbeats_stop() {
+        let mut presence_map = PresenceMap::default();
+        presence_map.update_at(&presence("alice", PresenceStatus::Online, NOW), NOW);
+        assert_eq!(presence_map.status_at("alice", NOW), PresenceStatus::Online);
+        let later = NOW + PRESENCE_TIMEOUT.as_millis() as i64;
+        assert_eq!(
+            presence_map.status_at("alice", later - 1),
+            PresenceStatus::Online
+        );
+        assert_eq!(
+            presence_map.status_at("alice", later),
+            PresenceStatus::Offline
+        );
+        assert_eq!(presence_map.status_at("bob", NOW), PresenceStatus::Offline);
+    }
+
+    #[test]
+    fn stale_presence_events_are_dropped() {
+        let mut presence_map = PresenceMap::default();
+        let replayed = presence("alice", PresenceStatus::Online, ago(PRESENCE_TIMEOUT));
+        presence_map.update_at(&replayed, NOW);
+        assert_eq!(
+            presence_map.status_at("alice", NOW),
+            PresenceStatus::Offline
+        );
+
+        // an older heartbeat arriving late doesn't undo a newer status
+        presence_map.update_at(
+            &presence("bob", PresenceStatus::Away, ago(HEARTBEAT_INTERVAL)),
+            NOW,
+        );
+        presence_map.update_at(
+            &presence("bob", PresenceStatus::Online, ago(2 * HEARTBEAT_INTERVAL)),
+            NOW,
+        );
+        assert_eq!(presence_map.status_at("bob", NOW), PresenceStatus::Away);
+    }
+
+    #[test]
+    fn presence_from_a_clock_ahead_still_times_out() {
+        let mut presence_map = PresenceMap::default();
+        let ahead = presence("alice", PresenceStatus::Online, NOW + 3_600_000);
+        presence_map.update_at(&ahead, NOW);
+        let later = NOW + PRESENCE_TIMEOUT.as_millis() as i64;
+        assert_eq!(
+            presence_map.status_at("alice", later),
+            PresenceStatus::Offline
+        );
+    }
+
+    fn typing(name: &str, room: &str, sent_at: i64) -> TypingEvent {
+        TypingEvent {
+            name: name.to_string(),
+            room: room.to_string(),
+            sent_at,
+        }
+    }
+
+    #[test]
+    fn typing_expires_and_stops() {
+        let mut indicators = TypingIndicators::default();
+        indicators.update_at(&typing("alice", "general", NOW), NOW);
+        indicators.update_at(&typing("bob", "general", NOW), NOW);
+        indicators.update_at(&typing("carol", "random", NOW), NOW);
+        assert_eq!(
+            indicators.typing_in_at("general", "dave", NOW),
+            ["alice", "bob"]
+        );
+        assert_eq!(indicators.typing_in_at("general", "bob", NOW), ["alice"]);
+
+        indicators.stopped("general", "alice");
+        assert_eq!(indicators.typing_in_at("general", "dave", NOW), ["bob"]);
+
+        let later = NOW + TYPING_EXPIRY.as_millis() as i64;
+        assert!(indicators.typing_in_at("general", "dave", later).is_empty());
+    }
+
+    #[test]
+    fn stale_typing_events_are_dropped() {
+        let mut indicators = TypingIndicators::default();
+        indicators.update_at(&typing("alice", "general", ago(TYPING_EXPIRY)), NOW);
+        assert!(indicators.typing_in_at("general", "bob", NOW).is_empty());
+
+        indicators.update_at(&typing("alice", "general", NOW), NOW);
+        indicators.update_at(&typing("alice", "general", ago(TYPING_THROTTLE)), NOW);
+        let later = NOW + TYPING_THROTTLE.as_millis() as i64;
+        assert_eq!(indicators.typing_in_at("general", "bob", later), ["alice"]);
+    }
+
+    #[test]
+    fn typing_events_are_throttled() {
+        assert!(typing_due(None));
+        assert!(!typing_due(Some(Instant::now())));
+        let earlier = Instant::now().checked_sub(TYPING_THROTTLE);
+        assert!(earlier.is_none_or(|earlier| typing_due(Some(earlier))));
+        // throttled events still keep the indicator up in between
+        assert!(TYPING_EXPIRY > TYPING_THROTTLE);
+    }
+}
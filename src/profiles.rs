@@ -0,0 +1,314 @@
+use crate::api::ApiClient;
+use crate::app_inputs::{InputMode, LoginInput};
+use crate::config::{Config, ConfigError, TransportConfig};
+use crate::db;
+use crate::e2e::{Identity, SigningIdentity};
+use crate::events::consumer;
+use crate::events::utils::{self, Transport};
+use crate::events::verifier::Verifier;
+use crate::events::wiggles_event::ReceivedEvent;
+use crate::model::models::{Message, WigglesUser};
+use crate::presence::{PresenceMap, TypingIndicators};
+use crate::rooms;
+use crate::search::Search;
+use crate::ui_render_handler;
+use crossbeam_channel::{unbounded, Receiver};
+use log::{error, warn};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::thread;
+
+/// Name of the profile made of the config file's top level sections
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Deployments the TUI can switch between.
+/// Each one connects the first time it is opened and keeps its consumer running after.
+#[derive(Default)]
+pub struct Profiles {
+    profiles: Vec<Profile>,
+    active: usize,
+}
+
+struct Profile {
+    name: String,
+    database_url: String,
+    /// HTTP service messages are stored through
+    api_url: String,
+    transport: TransportConfig,
+    connection: Option<Connection>,
+    /// Conversation state while another profile is shown
+    state: Option<ProfileState>,
+}
+
+struct Connection {
+    transport: Arc<Transport>,
+    /// Events from this profile's consumer, read while the profile is shown
+    receiver: Receiver<ReceivedEvent>,
+}
+
+impl Profiles {
+    /// The profile in use first, with the environment and flags applied, then the other ones
+    pub fn from_config(config: &Config) -> Profiles {
+        let active = config.profile.as_deref().unwrap_or(DEFAULT_PROFILE);
+        let mut profiles = vec![Profile::new(
+            active,
+            &config.database.url,
+            &config.http.url(),
+            &config.transport,
+        )];
+        for (name, profile) in &config.profiles {
+            if name != active {
+                let http = profile.http.as_ref().unwrap_or(&config.http);
+                profiles.push(Profile::new(
+                    name,
+                    &profile.database.url,
+                    &http.url(),
+                    &profile.transport,
+                ));
+            }
+        }
+        Profiles {
+            profiles,
+            active: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.profiles.len()
+    }
+
+    pub fn active(&self) -> usize {
+        self.active
+    }
+
+    pub fn active_name(&self) -> &str {
+        self.profiles
+            .get(self.active)
+            .map_or(DEFAULT_PROFILE, |profile| profile.name.as_str())
+    }
+
+    /// Base URL of the active profile's HTTP service
+    pub fn api_url(&self) -> &str {
+        self.profiles
+            .get(self.active)
+            .map_or("", |profile| profile.api_url.as_str())
+    }
+
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.profiles
+            .iter()
+            .position(|profile| profile.name == name)
+    }
+
+    /// Starts the profile's consumer unless it is already running
+    pub fn connect(&mut self, index: usize) -> Result<(), ConfigError> {
+        let profile = &mut self.profiles[index];
+        if profile.connection.is_some() {
+            return Ok(());
+        }
+        let transport = Arc::new(Transport::new(&profile.transport)?);
+        let (sender, receiver) = unbounded();
+        let consumer_transport = transport.clone();
+        let verifier = Verifier::new(&profile.database_url);
+        let name = profile.name.to_string();
+        thread::Builder::new()
+            .name(format!("kafka consumer thread ({})", name))
+            .spawn(move || {
+                if let Err(err) = consumer::start_consuming(&consumer_transport, verifier, sender) {
+                    error!("Consumer for profile {} stopped: {}", name, err);
+                }
+            })
+            .map_err(|err| ConfigError::Invalid(format!("could not start a consumer: {}", err)))?;
+        profile.connection = Some(Connection {
+            transport,
+            receiver,
+        });
+        Ok(())
+    }
+
+    /// Points the database and producers at a connected profile
+    pub fn activate(&mut self, index: usize) {
+        let profile = &self.profiles[index];
+        db::configure(&profile.database_url);
+        if let Some(connection) = &profile.connection {
+            utils::activate(connection.transport.clone());
+        }
+        self.active = index;
+    }
+
+    /// Events received by the active profile's consumer
+    pub fn receiver(&self) -> Option<Receiver<ReceivedEvent>> {
+        self.profiles
+            .get(self.active)
+            .and_then(|profile| profile.connection.as_ref())
+            .map(|connection| connection.receiver.clone())
+    }
+
+    pub fn take_state(&mut self, index: usize) -> Option<ProfileState> {
+        self.profiles[index].state.take()
+    }
+
+    pub fn store_state(&mut self, index: usize, state: ProfileState) {
+        self.profiles[index].state = Some(state);
+    }
+}
+
+impl Profile {
+    fn new(name: &str, database_url: &str, api_url: &str, transport: &TransportConfig) -> Profile {
+        Profile {
+            name: name.to_string(),
+            database_url: database_url.to_string(),
+            api_url: api_url.to_string(),
+            transport: transport.clone(),
+            connection: None,
+            state: None,
+        }
+    }
+}
+
+/// Everything in `App` that belongs to one deployment, swapped in and out on profile switches
+pub struct ProfileState {
+    pub input_mode: InputMode,
+    pub login_input_mode: LoginInput,
+    pub user: WigglesUser,
+    pub current_user: String,
+    pub messages: Vec<Message>,
+    pub user_names: Vec<String>,
+    pub rooms: Vec<String>,
+    pub current_room: String,
+    pub last_read: HashMap<String, i32>,
+    pub seen_by: HashMap<String, HashMap<String, i32>>,
+    pub first_unread: Option<i32>,
+    pub presence: PresenceMap,
+    pub typing: TypingIndicators,
+    pub search: Option<Search>,
+    pub jump_to: Option<i32>,
+    pub identity: Option<Identity>,
+    pub signing: Option<SigningIdentity>,
+    pub public_keys: HashMap<String, String>,
+    pub unverified: HashSet<i32>,
+    pub api: Option<ApiClient>,
+}
+
+impl ProfileState {
+    /// History, user names and public keys from the active profile's database, logged out
+    pub fn load() -> ProfileState {
+        let messages = Message::get().unwrap_or_else(|err| {
+            warn!("Failed to load message history: {}", err);
+            Vec::new()
+        });
+        let messages = ui_render_handler::remove_old_messages(messages);
+        let mut rooms = vec![rooms::default_room()];
+        for message in &messages {
+            if !rooms.contains(&message.room) {
+                rooms.push(message.room.to_string());
+            }
+        }
+        let users = WigglesUser::get().unwrap_or_default();
+        let public_keys = users
+            .iter()
+            .filter_map(|u| Some((u.name.to_string(), u.public_key.clone()?)))
+            .collect();
+        let user_names = users.into_iter().map(|u| u.name).collect();
+        ProfileState {
+            input_mode: InputMode::Normal,
+            login_input_mode: LoginInput::UserName,
+            user: WigglesUser::default(),
+            current_user: String::new(),
+            messages,
+            user_names,
+            rooms,
+            current_room: rooms::default_room(),
+            last_read: HashMap::new(),
+            seen_by: HashMap::new(),
+            first_unread: None,
+            presence: PresenceMap::default(),
+            typing: TypingIndicators::default(),
+            search: None,
+            jump_to: None,
+            identity: None,
+            signing: None,
+            public_keys,
+            unverified: HashSet::new(),
+            api: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str = r#"
+        [database]
+        url = "postgres://localhost/home"
+
+        [http]
+        bind = "127.0.0.1:8080"
+        url = "https://chat.home"
+
+        [profiles.work.database]
+        url = "postgres://db.work/chat"
+
+        [profiles.work.http]
+        url = "https://chat.work"
+
+        [profiles.play.database]
+        url = "postgres://db.play/chat"
+    "#;
+
+    fn profiles(selected: Option<&str>) -> Profiles {
+        let mut config: Config = toml::from_str(FILE).unwrap();
+        config.select_profile(selected.map(str::to_string)).unwrap();
+        Profiles::from_config(&config)
+    }
+
+    fn api_urls(profiles: &Profiles) -> Vec<(&str, &str)> {
+        profiles
+            .profiles
+            .iter()
+            .map(|profile| (profile.name.as_str(), profile.api_url.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn top_level_sections_are_the_default_profile() {
+        let profiles = profiles(None);
+        assert_eq!(profiles.active(), 0);
+        assert_eq!(profiles.active_name(), DEFAULT_PROFILE);
+        assert_eq!(profiles.api_url(), "https://chat.home");
+        assert_eq!(
+            api_urls(&profiles),
+            [
+                ("default", "https://chat.home"),
+                ("play", "https://chat.home"),
+                ("work", "https://chat.work"),
+            ]
+        );
+        assert_eq!(profiles.profiles[1].database_url, "postgres://db.play/chat");
+    }
+
+    #[test]
+    fn the_selected_profile_comes_first() {
+        let profiles = profiles(Some("work"));
+        assert_eq!(profiles.len(), 2);
+        assert_eq!(profiles.active_name(), "work");
+        assert_eq!(profiles.position("work"), Some(0));
+        assert_eq!(profiles.position("play"), Some(1));
+        assert_eq!(profiles.position(DEFAULT_PROFILE), None);
+        // play has no `[http]` of its own, so it still gets the top level one, not work's
+        assert_eq!(
+            api_urls(&profiles),
+            [("work", "https://chat.work"), ("play", "https://chat.home")]
+        );
+    }
+
+    #[test]
+    fn without_profiles_the_default_one_is_active() {
+        let profiles = Profiles::from_config(&Config::default());
+        assert_eq!(profiles.len(), 1);
+        assert_eq!(profiles.active_name(), DEFAULT_PROFILE);
+        assert_eq!(profiles.position(DEFAULT_PROFILE), Some(0));
+        assert_eq!(Profiles::default().active_name(), DEFAULT_PROFILE);
+    }
+}
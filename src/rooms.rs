@@ -0,0 +1,130 @@
+pub const DEFAULT_ROOM: &str = "general";
+const DM_PREFIX: &str = "dm:";
+const DM_SEPARATOR: char = ':';
+
+pub fn default_room() -> String {
+    DEFAULT_ROOM.to_string()
+}
+
+/// Room name for the direct message conversation between two users.
+/// Both participants get the same name regardless of who starts it.
+pub fn dm_room(user: &str, other: &str) -> String {
+    let (first, second) = match user <= other {
+        true => (user, other),
+        false => (other, user),
+    };
+    format!("{}{}{}{}", DM_PREFIX, first, DM_SEPARATOR, second)
+}
+
+/// Both participants of a direct message room
+pub fn dm_participants(room: &str) -> Option<(&str, &str)> {
+    room.strip_prefix(DM_PREFIX)?.split_once(DM_SEPARATOR)
+}
+
+/// The other participant of a direct message room the user is in
+pub fn dm_peer<'a>(room: &'a str, user: &str) -> Option<&'a str> {
+    match dm_participants(room)? {
+        (first, second) if first == user => Some(second),
+        (first, second) if second == user => Some(first),
+        _ => None,
+    }
+}
+
+pub fn is_dm(room: &str) -> bool {
+    dm_participants(room).is_some()
+}
+
+/// Rooms are open to everyone, direct messages only to their participants
+pub fn is_visible(room: &str, user: &str) -> bool {
+    match dm_participants(room) {
+        Some((first, second)) => first == user || second == user,
+        None => true,
+    }
+}
+
+/// SQL `LIKE` pattern matching every direct message room
+pub fn dm_pattern() -> String {
+    format!("{}%", DM_PREFIX)
+}
+
+/// SQL `LIKE` patterns matching the direct message rooms `user` is in,
+/// with wildcards in the name escaped by `\`
+pub fn dm_patterns_for(user: &str) -> [String; 2] {
+    let user = user
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    [
+        format!("{}{}{}%", DM_PREFIX, user, DM_SEPARATOR),
+        format!("{}%{}{}", DM_PREFIX, DM_SEPARATOR, user),
+    ]
+}
+
+/// `#room` for rooms and `@other` for direct messages
+pub fn display_name(room: &str, user: &str) -> String {
+    match dm_participants(room) {
+        Some((first, second)) => format!("@{}", if first == user { second } else { first }),
+        None => format!("#{}", room),
+    }
+}
+
+/// Room names are a single word, so they can't be mistaken for direct messages
+pub fn is_valid_name(room: &str) -> bool {
+    !room.is_empty()
+        && room
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+}
+
+/// User names end up in direct message room names, so they can't hold the separator,
+/// and in commands and mentions, so they can't hold whitespace either
+pub fn is_valid_user_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(DM_SEPARATOR) && !name.contains(char::is_whitespace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn both_participants_get_the_same_dm_room() {
+        assert_eq!(dm_room("bob", "alice"), "dm:alice:bob");
+        assert_eq!(dm_room("alice", "bob"), "dm:alice:bob");
+        assert_eq!(dm_participants("dm:alice:bob"), Some(("alice", "bob")));
+        assert_eq!(dm_participants("general"), None);
+    }
+
+    #[test]
+    fn dm_rooms_are_only_visible_to_their_participants() {
+        assert_eq!(dm_peer("dm:alice:bob", "alice"), Some("bob"));
+        assert_eq!(dm_peer("dm:alice:bob", "carol"), None);
+        assert!(is_visible("dm:alice:bob", "bob"));
+        assert!(!is_visible("dm:alice:bob", "carol"));
+        assert!(is_visible("general", "carol"));
+    }
+
+    #[test]
+    fn dm_patterns_escape_wildcards() {
+        assert_eq!(dm_patterns_for("a_b%"), ["dm:a\\_b\\%:%", "dm:%:a\\_b\\%"]);
+        assert_eq!(dm_pattern(), "dm:%");
+    }
+
+    #[test]
+    fn room_names_are_single_words() {
+        assert!(is_valid_name("release-2_0"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name("dm:alice:bob"));
+        assert!(!is_valid_name("two words"));
+        assert_eq!(display_name("dm:alice:bob", "bob"), "@alice");
+        assert_eq!(display_name("general", "bob"), "#general");
+    }
+
+    #[test]
+    fn user_names_cant_break_dm_room_names() {
+        assert!(is_valid_user_name("alice.b-2"));
+        assert!(!is_valid_user_name(""));
+        assert!(!is_valid_user_name("a:b"));
+        assert!(!is_valid_user_name("alice b"));
+        assert!(!is_valid_user_name("alice\t"));
+    }
+}
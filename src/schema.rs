@@ -1,9 +1,27 @@
 table! {
     message (id) {
         id -> Int4,
-        name -> Varchar,
         body -> Text,
         published -> Bool,
+        room -> Varchar,
+        created_at -> Timestamptz,
+        author_email -> Varchar,
+    }
+}
+
+table! {
+    read_receipt (user_name, room) {
+        user_name -> Varchar,
+        room -> Varchar,
+        last_read_message_id -> Int4,
+    }
+}
+
+table! {
+    wiggles_session (token_hash) {
+        token_hash -> Varchar,
+        user_email -> Varchar,
+        expires_at -> Timestamptz,
     }
 }
 
@@ -13,10 +31,17 @@ table! {
         name -> Varchar,
         password -> Varchar,
         email -> Varchar,
+        public_key -> Nullable<Varchar>,
+        signing_key -> Nullable<Varchar>,
     }
 }
 
+joinable!(message -> wiggles_user (author_email));
+joinable!(wiggles_session -> wiggles_user (user_email));
+
 allow_tables_to_appear_in_same_query!(
     message,
+    read_receipt,
+    wiggles_session,
     wiggles_user,
 );
@@ -0,0 +1,110 @@
+use crate::model::models::Message;
+use std::ops::Range;
+
+/// Most results one search shows
+pub const RESULT_LIMIT: i64 = 50;
+/// Messages shown either side of a result opened outside the loaded history
+pub const CONTEXT_RADIUS: usize = 5;
+
+/// Results of the last search run from the TUI
+#[derive(Default)]
+pub struct Search {
+    /// Query the results are for
+    pub query: String,
+    pub results: Vec<Message>,
+    pub selected: usize,
+    /// Messages around the selected result, when it is older than the loaded history
+    pub context: Option<Vec<Message>>,
+}
+
+impl Search {
+    pub fn selected_result(&self) -> Option<&Message> {
+        self.results.get(self.selected)
+    }
+
+    /// Moves the selection, wrapping around at either end
+    pub fn select(&mut self, forward: bool) {
+        let count = self.results.len();
+        if count == 0 {
+            return;
+        }
+        self.selected = match forward {
+            true => (self.selected + 1) % count,
+            false => (self.selected + count - 1) % count,
+        };
+        self.context = None;
+    }
+
+    /// Lowercased words of the query, leaving out `-excluded` words and the `or` operator.
+    /// Split into words the way `matches` splits messages, so `e-mail` finds `e` and `mail`.
+    pub fn terms(&self) -> Vec<String> {
+        self.query
+            .split_whitespace()
+            .filter(|part| !part.starts_with('-'))
+            .flat_map(|part| words(part).map(move |word| part[word].to_lowercase()))
+            .filter(|word| word != "or")
+            .collect()
+    }
+}
+
+/// Byte ranges of the words in `text` that start with one of the terms.
+/// Postgres matches stemmed words, so this only approximates what it matched.
+pub fn matches(text: &str, terms: &[String]) -> Vec<Range<usize>> {
+    words(text)
+        .filter(|word| {
+            let word = text[word.clone()].to_lowercase();
+            terms.iter().any(|term| word.starts_with(term.as_str()))
+        })
+        .collect()
+}
+
+// Byte ranges of the runs of alphanumeric characters in `text`
+fn words(text: &str) -> impl Iterator<Item = Range<usize>> + '_ {
+    let mut word_start = None;
+    text.char_indices()
+        .chain(std::iter::once((text.len(), ' ')))
+        .filter_map(move |(i, c)| match (c.is_alphanumeric(), word_start) {
+            (true, None) => {
+                word_start = Some(i);
+                None
+            }
+            (false, Some(start)) => {
+                word_start = None;
+                Some(start..i)
+            }
+            _ => None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terms(query: &str) -> Vec<String> {
+        Search {
+            query: query.to_string(),
+            ..Search::default()
+        }
+        .terms()
+    }
+
+    #[test]
+    fn terms_leave_out_operators_and_exclusions() {
+        assert_eq!(terms("Deploy OR rollback -staging"), ["deploy", "rollback"]);
+        assert_eq!(terms("\"release notes\""), ["release", "notes"]);
+        assert_eq!(terms("e-mail"), ["e", "mail"]);
+        assert!(terms("  -only ").is_empty());
+    }
+
+    #[test]
+    fn matches_find_words_starting_with_a_term() {
+        let text = "Deploying the e-mail service, café!";
+        let found: Vec<&str> = matches(text, &terms("deploy e-mail CAFÉ"))
+            .into_iter()
+            .map(|range| &text[range])
+            .collect();
+        assert_eq!(found, ["Deploying", "e", "mail", "café"]);
+        assert!(matches("redeploy", &terms("deploy")).is_empty());
+        assert!(matches(text, &[]).is_empty());
+    }
+}
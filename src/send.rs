@@ -0,0 +1,210 @@
+use crate::api::{ApiClient, KeyUpdate, NewMessage};
+use crate::credentials::{CredentialStore, SigningKeys};
+use crate::e2e::SigningIdentity;
+use crate::error_handler::CustomError;
+use crate::events::wiggles_event::WigglesEvent;
+use crate::events::{producer, utils};
+use crate::model::models::{Message, WigglesUser};
+use crate::rooms;
+use std::error::Error;
+use std::io::{self, Read};
+use thiserror::Error;
+
+/// Why `wiggles send` failed, each with its own exit code for scripts
+#[derive(Debug, Error)]
+pub enum SendError {
+    #[error("{0}")]
+    Invalid(String),
+    #[error("could not read the message from stdin: {0}")]
+    Read(#[from] io::Error),
+    #[error("wrong email or password for {0}")]
+    Authentication(String),
+    #[error("could not store the message: {0}")]
+    Store(#[from] CustomError),
+    #[error("the message was stored but could not be delivered: {0}")]
+    Publish(Box<dyn Error>),
+}
+
+impl SendError {
+    /// 2 for bad input, 3 for failed authentication, 4 when nothing was stored
+    /// and 5 when the message was stored but not delivered
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            SendError::Invalid(_) | SendError::Read(_) => 2,
+            SendError::Authentication(_) => 3,
+            SendError::Store(_) => 4,
+            SendError::Publish(_) => 5,
+        }
+    }
+}
+
+/// The message body from the arguments, or everything on stdin without the final newline
+pub fn read_body(argument: Option<&str>) -> Result<String, SendError> {
+    let body = match argument {
+        Some(body) => body.to_string(),
+        None => {
+            let mut body = String::new();
+            io::stdin().read_to_string(&mut body)?;
+            body.trim_end_matches(&['\r', '\n'][..]).to_string()
+        }
+    };
+    match body.trim().is_empty() {
+        true => Err(SendError::Invalid("the message is empty".to_string())),
+        false => Ok(body),
+    }
+}
+
+/// Starts a session with the HTTP service, which checks the password and stores the message
+pub fn authenticate(
+    url: &str,
+    email: &str,
+    password: &str,
+) -> Result<(ApiClient, WigglesUser), SendError> {
+    let api = ApiClient::log_in(url, email, password, false).map_err(|err| {
+        match err.error_status_code {
+            401 => SendError::Authentication(email.to_string()),
+            _ => SendError::Store(err),
+        }
+    })?;
+    match WigglesUser::find_by_email(api.email())? {
+        Some(user) => Ok((api, user)),
+        None => Err(SendError::Authentication(email.to_string())),
+    }
+}
+
+/// The key to sign with, as on a TUI login: the one kept here, or a new one for a user
+/// who hasn't published any. Consumers would drop the message if it were signed with another
+/// key than the published one, so that is refused.
+pub fn signing_identity(
+    api: &ApiClient,
+    user: &WigglesUser,
+    profile: &str,
+    keys: &mut SigningKeys,
+    credentials: Option<&CredentialStore>,
+) -> Result<SigningIdentity, SendError> {
+    let published = user.signing_key.as_deref();
+    let saved = keys.get(profile, &user.name);
+    let is_saved = saved.is_some();
+    // earlier versions derived the signing key from the direct message key, it carries over
+    let carried_over = || {
+        Some(
+            credentials?
+                .identity(profile, &user.name)?
+                .signing_identity(),
+        )
+    };
+    let signing = match (saved.or_else(carried_over), published) {
+        (Some(signing), Some(published)) if signing.public_base64() == published => signing,
+        (_, Some(_)) => {
+            return Err(SendError::Invalid(format!(
+                "{} signs their messages with a key that isn't kept here, \
+                 type /newkey in the TUI to replace it",
+                user.name
+            )))
+        }
+        (signing, None) => signing.unwrap_or_else(SigningIdentity::generate),
+    };
+    if !is_saved {
+        keys.set(profile, &user.name, &signing);
+        keys.save()
+            .map_err(|err| SendError::Invalid(err.to_string()))?;
+    }
+    if published.is_none() {
+        api.publish_keys(&KeyUpdate {
+            signing_key: Some(signing.public_base64()),
+            ..KeyUpdate::default()
+        })?;
+    }
+    Ok(signing)
+}
+
+/// Stores the message through the HTTP service as the session's user,
+/// then publishes it and waits for the broker to confirm delivery
+pub fn send(
+    api: &ApiClient,
+    room: &str,
+    body: String,
+    signing: &SigningIdentity,
+) -> Result<Message, SendError> {
+    if !rooms::is_valid_name(room) {
+        return Err(SendError::Invalid(format!("invalid room name `{}`", room)));
+    }
+    let message = api.send_message(&NewMessage {
+        id: None,
+        room: room.to_string(),
+        body,
+    })?;
+    let payload = WigglesEvent::Message(message.clone()).to_signed_payload(signing, api.email());
+    producer::produce_event(&utils::active(), payload).map_err(SendError::Publish)?;
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn api() -> ApiClient {
+        ApiClient::resume("http://127.0.0.1:1", "token", "alice@example.com")
+    }
+
+    fn alice(signing_key: Option<String>) -> WigglesUser {
+        WigglesUser {
+            name: "alice".to_string(),
+            email: "alice@example.com".to_string(),
+            signing_key,
+            ..WigglesUser::default()
+        }
+    }
+
+    // Nothing is written to it, the tests only keep keys in memory
+    fn signing_keys() -> SigningKeys {
+        let path = env::temp_dir().join(format!("wiggles-test-unsaved-{}", std::process::id()));
+        SigningKeys::load(&path).unwrap()
+    }
+
+    #[test]
+    fn each_failure_has_its_own_exit_code() {
+        let io_error = || io::Error::from(io::ErrorKind::UnexpectedEof);
+        let codes = [
+            SendError::Invalid("empty".to_string()),
+            SendError::Read(io_error()),
+            SendError::Authentication("alice@example.com".to_string()),
+            SendError::Store(CustomError::new(500, "down".to_string())),
+            SendError::Publish(Box::new(io_error())),
+        ]
+        .map(|err| err.exit_code());
+        assert_eq!(codes, [2, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn messages_are_signed_with_the_published_key_only() {
+        let published = SigningIdentity::generate();
+        let mut keys = signing_keys();
+
+        // the key was published from elsewhere and isn't kept here
+        let user = alice(Some(published.public_base64()));
+        let refused = signing_identity(&api(), &user, "default", &mut keys, None);
+        assert!(matches!(&refused, Err(SendError::Invalid(_))));
+        assert_eq!(refused.err().map(|err| err.exit_code()), Some(2));
+
+        // another key kept here would get the message dropped too
+        keys.set("default", "alice", &SigningIdentity::generate());
+        assert!(signing_identity(&api(), &user, "default", &mut keys, None).is_err());
+
+        keys.set("default", "alice", &published);
+        let signing = signing_identity(&api(), &user, "default", &mut keys, None).unwrap();
+        assert_eq!(signing.public_base64(), published.public_base64());
+    }
+
+    #[test]
+    fn rooms_are_checked_before_anything_is_sent() {
+        let sent = send(
+            &api(),
+            "two words",
+            "hi".to_string(),
+            &SigningIdentity::generate(),
+        );
+        assert_eq!(sent.err().map(|err| err.exit_code()), Some(2));
+    }
+}
@@ -0,0 +1,192 @@
+use crate::db;
+use crate::events::verifier::Verifier;
+use crate::events::wiggles_event::WigglesEvent;
+use crate::events::{consumer, utils};
+use crate::export::{self, ExportError, ExportFormat};
+use crate::model::models::{Message, MessageFilter};
+use crossbeam_channel::unbounded;
+use std::collections::HashSet;
+use std::error::Error;
+use std::io::{self, ErrorKind, Write};
+use std::thread;
+
+pub struct TailOptions {
+    /// `Transcript` for text lines or `JsonLines`
+    pub format: ExportFormat,
+    pub filter: MessageFilter,
+    /// Print the stored messages matching the filter before the live ones
+    pub history: bool,
+}
+
+/// Prints messages from the consumer as they arrive until the output is closed
+pub fn tail(options: &TailOptions) -> Result<(), Box<dyn Error>> {
+    // the consumer starts before history is read, but it only receives what is sent once its
+    // partitions are assigned. A message sent in between is in neither, so a few seconds right
+    // after startup can be missing from the output.
+    let (sender, receiver) = unbounded();
+    let transport = utils::active();
+    let verifier = Verifier::new(&db::url());
+    thread::Builder::new()
+        .name("kafka consumer thread".to_string())
+        .spawn(move || consumer::start_consuming(&transport, verifier, sender).unwrap())?;
+
+    let history = match options.history {
+        true => Message::filtered(&options.filter)?,
+        false => Vec::new(),
+    };
+    let live = receiver
+        .into_iter()
+        .filter_map(|received| match received.event {
+            WigglesEvent::Message(message) => Some(message),
+            _ => None,
+        });
+    let stdout = io::stdout();
+    follow(history.into_iter().chain(live), options, stdout.lock())?;
+    Ok(())
+}
+
+// Writes the messages that pass the filter until they run out or the reader goes away,
+// like `head` closing the pipe
+fn follow<W: Write>(
+    messages: impl IntoIterator<Item = Message>,
+    options: &TailOptions,
+    mut out: W,
+) -> Result<(), ExportError> {
+    // history and the transport can both carry a message, print it once
+    let mut printed = HashSet::new();
+    for message in messages {
+        if !options.filter.matches(&message) || !printed.insert(message.id) {
+            continue;
+        }
+        let written =
+            export::write_messages(std::slice::from_ref(&message), options.format, &mut out)
+                .and_then(|()| out.flush().map_err(ExportError::Io));
+        match written {
+            Ok(()) => {}
+            Err(ExportError::Io(err)) if err.kind() == ErrorKind::BrokenPipe => break,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    fn message(id: i32, name: &str, room: &str, hour: u32) -> Message {
+        Message {
+            id,
+            name: name.to_string(),
+            body: format!("message {}", id),
+            published: true,
+            room: room.to_string(),
+            created_at: Utc.with_ymd_and_hms(2024, 5, 1, hour, 0, 0).unwrap(),
+            author_email: format!("{}@example.com", name),
+        }
+    }
+
+    fn tail_ids(messages: Vec<Message>, filter: MessageFilter) -> Vec<i32> {
+        let options = TailOptions {
+            format: ExportFormat::JsonLines,
+            filter,
+            history: false,
+        };
+        let mut out = Vec::new();
+        follow(messages, &options, &mut out).unwrap();
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<Message>(line).unwrap().id)
+            .collect()
+    }
+
+    fn messages() -> Vec<Message> {
+        vec![
+            message(1, "alice", "general", 9),
+            message(2, "bob", "random", 10),
+            message(3, "bob", "general", 11),
+            message(4, "carol", "dm:bob:carol", 12),
+            // also in the history
+            message(1, "alice", "general", 9),
+        ]
+    }
+
+    #[test]
+    fn every_message_is_printed_once() {
+        assert_eq!(tail_ids(messages(), MessageFilter::default()), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn filters_narrow_down_what_is_printed() {
+        let by_room = MessageFilter {
+            room: Some("general".to_string()),
+            ..MessageFilter::default()
+        };
+        assert_eq!(tail_ids(messages(), by_room), [1, 3]);
+
+        let by_email = MessageFilter {
+            user: Some("bob@example.com".to_string()),
+            ..MessageFilter::default()
+        };
+        assert_eq!(tail_ids(messages(), by_email), [2, 3]);
+
+        let since = MessageFilter {
+            since: Some(Utc.with_ymd_and_hms(2024, 5, 1, 10, 0, 0).unwrap()),
+            ..MessageFilter::default()
+        };
+        assert_eq!(tail_ids(messages(), since), [2, 3, 4]);
+
+        let visible = MessageFilter {
+            visible_to: Some("alice".to_string()),
+            ..MessageFilter::default()
+        };
+        assert_eq!(tail_ids(messages(), visible), [1, 2, 3]);
+    }
+
+    #[test]
+    fn json_lines_are_one_message_each() {
+        let options = TailOptions {
+            format: ExportFormat::JsonLines,
+            filter: MessageFilter::default(),
+            history: false,
+        };
+        let mut out = Vec::new();
+        follow(vec![message(1, "alice", "general", 9)], &options, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.ends_with("}\n"));
+        assert_eq!(out.lines().count(), 1);
+        let parsed: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(parsed["name"], "alice");
+        assert_eq!(parsed["room"], "general");
+        assert_eq!(parsed["created_at"], "2024-05-01T09:00:00Z");
+    }
+
+    // A reader that has gone away after the first message
+    struct ClosedPipe(usize);
+
+    impl Write for ClosedPipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            match self.0 {
+                0 => Err(io::Error::from(ErrorKind::BrokenPipe)),
+                _ => Ok(buf.len()),
+            }
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.0 = self.0.saturating_sub(1);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn a_closed_pipe_ends_the_tail() {
+        let options = TailOptions {
+            format: ExportFormat::Transcript,
+            filter: MessageFilter::default(),
+            history: false,
+        };
+        assert!(follow(messages(), &options, ClosedPipe(1)).is_ok());
+    }
+}
@@ -0,0 +1,436 @@
+use crate::config::ConfigError;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use tui::style::{Color, Modifier, Style};
+
+pub const BUILT_IN_THEMES: [&str; 4] = ["dark", "light", "high-contrast", "no-color"];
+
+/// `[theme]` section of the config file
+///
+/// ```toml
+/// [theme]
+/// name = "light"
+/// author_palette = ["blue", "#d75f00"]
+///
+/// [theme.colors]
+/// border = "dark_gray"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ThemeConfig {
+    /// One of the built in themes, `dark` when unset
+    pub name: Option<String>,
+    /// Colors assigned to message authors
+    pub author_palette: Option<Vec<String>>,
+    /// Foreground color overrides keyed by theme slot, e.g. `border = "blue"`
+    pub colors: HashMap<String, String>,
+}
+
+/// Styles for every widget the UI renders
+#[derive(Clone, Debug)]
+pub struct Theme {
+    /// Help bar in normal mode
+    pub help: Style,
+    /// Key names in the help bar
+    pub help_key: Style,
+    /// Input box text while not typing
+    pub input: Style,
+    /// Input box text while typing or logging in
+    pub input_active: Style,
+    /// Input and copyright borders
+    pub border: Style,
+    /// Messages, home, room and member list borders
+    pub block: Style,
+    /// Border of the pane navigation keys act on
+    pub focus: Style,
+    /// Open room in the room list
+    pub selected: Style,
+    /// Rooms with unread messages
+    pub unread: Style,
+    /// Typing indicator line under the messages
+    pub status: Style,
+    /// Member list status markers
+    pub presence_online: Style,
+    pub presence_away: Style,
+    pub presence_offline: Style,
+    /// Message and copyright text
+    pub text: Style,
+    /// Tagline on the home screen
+    pub tagline: Style,
+    /// Hints on the home screen
+    pub hint: Style,
+    /// Inline code and fenced code blocks
+    pub code: Style,
+    /// Block quote text
+    pub quote: Style,
+    /// Block quote gutter
+    pub quote_marker: Style,
+    /// Link labels
+    pub link: Style,
+    /// Link targets shown after the label
+    pub link_url: Style,
+    /// @mentions in message bodies
+    pub mention: Style,
+    /// Gutter marking messages that mention the logged in user
+    pub mention_marker: Style,
+    /// Words matching the search query
+    pub search_match: Style,
+    /// Colors assigned to message authors
+    pub author_palette: Vec<Color>,
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme::dark()
+    }
+}
+
+impl Theme {
+    /// Builds the configured theme. `NO_COLOR` wins over any configured colors.
+    pub fn from_config(config: &ThemeConfig) -> Result<Theme, ConfigError> {
+        if no_color_requested() {
+            return Ok(Theme::no_color());
+        }
+        let name = config.name.as_deref().unwrap_or("dark");
+        let mut theme = Theme::built_in(name).ok_or_else(|| {
+            ConfigError::Invalid(format!(
+                "unknown theme `{}`, expected one of {}",
+                name,
+                BUILT_IN_THEMES.join(", ")
+            ))
+        })?;
+
+        if let Some(palette) = &config.author_palette {
+            theme.author_palette = palette
+                .iter()
+                .map(|value| color_setting("theme.author_palette", value))
+                .collect::<Result<_, _>>()?;
+        }
+        for (slot, value) in &config.colors {
+            let color = color_setting(&format!("theme.colors.{}", slot), value)?;
+            let style = theme.slot_mut(slot).ok_or_else(|| {
+                ConfigError::Invalid(format!("unknown theme color `theme.colors.{}`", slot))
+            })?;
+            *style = style.fg(color);
+        }
+        Ok(theme)
+    }
+
+    pub fn built_in(name: &str) -> Option<Theme> {
+        match name {
+            "dark" => Some(Theme::dark()),
+            "light" => Some(Theme::light()),
+            "high-contrast" => Some(Theme::high_contrast()),
+            "no-color" => Some(Theme::no_color()),
+            _ => None,
+        }
+    }
+
+    pub fn dark() -> Theme {
+        Theme {
+            help: Style::default().add_modifier(Modifier::RAPID_BLINK),
+            help_key: Style::default().add_modifier(Modifier::BOLD),
+            input: Style::default(),
+            input_active: Style::default().fg(Color::Yellow),
+            border: Style::default().fg(Color::LightBlue),
+            block: Style::default().fg(Color::Blue),
+            focus: Style::default().fg(Color::Yellow),
+            selected: Style::default().add_modifier(Modifier::REVERSED),
+            unread: Style::default()
+                .fg(Color::White)
+                .add_modifier(Modifier::BOLD),
+            status: Style::default()
+                .fg(Color::Gray)
+                .add_modifier(Modifier::ITALIC),
+            presence_online: Style::default().fg(Color::LightGreen),
+            presence_away: Style::default().fg(Color::Yellow),
+            presence_offline: Style::default().fg(Color::DarkGray),
+            text: Style::default().fg(Color::LightCyan),
+            tagline: Style::default().fg(Color::LightBlue),
+            hint: Style::default().fg(Color::White),
+            code: Style::default().fg(Color::Yellow).bg(Color::Black),
+            quote: Style::default()
+                .fg(Color::Gray)
+                .add_modifier(Modifier::ITALIC),
+            quote_marker: Style::default().fg(Color::Gray),
+            link: Style::default()
+                .fg(Color::LightBlue)
+                .add_modifier(Modifier::UNDERLINED),
+            link_url: Style::default().fg(Color::DarkGray),
+            mention: Style::default()
+                .fg(Color::LightMagenta)
+                .add_modifier(Modifier::BOLD),
+            mention_marker: Style::default().fg(Color::LightMagenta),
+            search_match: Style::default().fg(Color::Black).bg(Color::LightYellow),
+            author_palette: vec![
+                Color::LightCyan,
+                Color::LightGreen,
+                Color::LightMagenta,
+                Color::LightYellow,
+                Color::LightRed,
+                Color::LightBlue,
+                Color::Cyan,
+                Color::Magenta,
+            ],
+        }
+    }
+
+    pub fn light() -> Theme {
+        Theme {
+            help: Style::default().fg(Color::Black),
+            help_key: Style::default().add_modifier(Modifier::BOLD),
+            input: Style::default().fg(Color::Black),
+            input_active: Style::default().fg(Color::Blue),
+            border: Style::default().fg(Color::Blue),
+            block: Style::default().fg(Color::DarkGray),
+            focus: Style::default()
+                .fg(Color::Blue)
+                .add_modifier(Modifier::BOLD),
+            selected: Style::default().add_modifier(Modifier::REVERSED),
+            unread: Style::default()
+                .fg(Color::Black)
+                .add_modifier(Modifier::BOLD),
+            status: Style::default()
+                .fg(Color::DarkGray)
+                .add_modifier(Modifier::ITALIC),
+            presence_online: Style::default().fg(Color::Green),
+            presence_away: Style::default().fg(Color::Rgb(0xd7, 0x87, 0x00)),
+            presence_offline: Style::default().fg(Color::Gray),
+            text: Style::default().fg(Color::Black),
+            tagline: Style::default().fg(Color::Blue),
+            hint: Style::default().fg(Color::DarkGray),
+            code: Style::default().fg(Color::Red),
+            quote: Style::default()
+                .fg(Color::DarkGray)
+                .add_modifier(Modifier::ITALIC),
+            quote_marker: Style::default().fg(Color::DarkGray),
+            link: Style::default()
+                .fg(Color::Blue)
+                .add_modifier(Modifier::UNDERLINED),
+            link_url: Style::default().fg(Color::Gray),
+            mention: Style::default()
+                .fg(Color::Magenta)
+                .add_modifier(Modifier::BOLD),
+            mention_marker: Style::default().fg(Color::Magenta),
+            search_match: Style::default().fg(Color::Black).bg(Color::Yellow),
+            author_palette: vec![
+                Color::Blue,
+                Color::Green,
+                Color::Magenta,
+                Color::Red,
+                Color::Cyan,
+                Color::Rgb(0xd7, 0x5f, 0x00),
+            ],
+        }
+    }
+
+    pub fn high_contrast() -> Theme {
+        let bold = Style::default().add_modifier(Modifier::BOLD);
+        Theme {
+            help: bold.fg(Color::White),
+            help_key: bold.fg(Color::Yellow),
+            input: Style::default().fg(Color::White),
+            input_active: bold.fg(Color::Yellow),
+            border: bold.fg(Color::White),
+            block: bold.fg(Color::White),
+            focus: bold.fg(Color::Yellow),
+            selected: bold.fg(Color::Black).bg(Color::Yellow),
+            unread: bold.fg(Color::LightYellow),
+            status: bold.fg(Color::White),
+            presence_online: bold.fg(Color::LightGreen),
+            presence_away: bold.fg(Color::Yellow),
+            presence_offline: Style::default().fg(Color::White),
+            text: Style::default().fg(Color::White),
+            tagline: bold.fg(Color::Yellow),
+            hint: bold.fg(Color::White),
+            code: Style::default().fg(Color::Black).bg(Color::White),
+            quote: Style::default().fg(Color::White),
+            quote_marker: bold.fg(Color::Yellow),
+            link: bold.fg(Color::LightCyan).add_modifier(Modifier::UNDERLINED),
+            link_url: Style::default().fg(Color::White),
+            mention: bold.fg(Color::Black).bg(Color::Yellow),
+            mention_marker: bold.fg(Color::Yellow),
+            search_match: bold.fg(Color::Black).bg(Color::LightCyan),
+            author_palette: vec![
+                Color::White,
+                Color::LightYellow,
+                Color::LightCyan,
+                Color::LightGreen,
+                Color::LightMagenta,
+            ],
+        }
+    }
+
+    /// Modifiers only, for terminals without color and users setting `NO_COLOR`
+    pub fn no_color() -> Theme {
+        let plain = Style::default();
+        let bold = plain.add_modifier(Modifier::BOLD);
+        Theme {
+            help: plain,
+            help_key: bold,
+            input: plain,
+            input_active: plain,
+            border: plain,
+            block: plain,
+            focus: bold,
+            selected: plain.add_modifier(Modifier::REVERSED),
+            unread: bold,
+            status: plain.add_modifier(Modifier::ITALIC),
+            presence_online: bold,
+            presence_away: plain,
+            presence_offline: plain,
+            text: plain,
+            tagline: plain,
+            hint: plain,
+            code: plain.add_modifier(Modifier::REVERSED),
+            quote: plain.add_modifier(Modifier::ITALIC),
+            quote_marker: plain,
+            link: plain.add_modifier(Modifier::UNDERLINED),
+            link_url: plain,
+            mention: bold,
+            mention_marker: bold,
+            search_match: bold.add_modifier(Modifier::REVERSED),
+            author_palette: vec![Color::Reset],
+        }
+    }
+
+    /// Picks a stable color for an author by hashing their name into the palette
+    pub fn author_color(&self, name: &str) -> Color {
+        if self.author_palette.is_empty() {
+            return Color::Reset;
+        }
+        // FNV-1a, so an author keeps their color across runs and builds
+        let hash = name.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+        self.author_palette[(hash % self.author_palette.len() as u64) as usize]
+    }
+
+    fn slot_mut(&mut self, slot: &str) -> Option<&mut Style> {
+        let style = match slot {
+            "help" => &mut self.help,
+            "help_key" => &mut self.help_key,
+            "input" => &mut self.input,
+            "input_active" => &mut self.input_active,
+            "border" => &mut self.border,
+            "block" => &mut self.block,
+            "focus" => &mut self.focus,
+            "selected" => &mut self.selected,
+            "unread" => &mut self.unread,
+            "status" => &mut self.status,
+            "presence_online" => &mut self.presence_online,
+            "presence_away" => &mut self.presence_away,
+            "presence_offline" => &mut self.presence_offline,
+            "text" => &mut self.text,
+            "tagline" => &mut self.tagline,
+            "hint" => &mut self.hint,
+            "code" => &mut self.code,
+            "quote" => &mut self.quote,
+            "quote_marker" => &mut self.quote_marker,
+            "link" => &mut self.link,
+            "link_url" => &mut self.link_url,
+            "mention" => &mut self.mention,
+            "mention_marker" => &mut self.mention_marker,
+            "search_match" => &mut self.search_match,
+            _ => return None,
+        };
+        Some(style)
+    }
+}
+
+/// https://no-color.org: any non-empty `NO_COLOR` disables color
+fn no_color_requested() -> bool {
+    env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+}
+
+fn color_setting(key: &str, value: &str) -> Result<Color, ConfigError> {
+    parse_color(value)
+        .ok_or_else(|| ConfigError::Invalid(format!("`{}` has an unknown color `{}`", key, value)))
+}
+
+/// Parses a color name (`light_cyan`, `LightCyan`) or `#rrggbb` hex value
+pub fn parse_color(value: &str) -> Option<Color> {
+    if let Some(hex) = value.strip_prefix('#') {
+        if hex.len() != 6 {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+        return Some(Color::Rgb(channel(0)?, channel(2)?, channel(4)?));
+    }
+    let normalized: String = value
+        .chars()
+        .filter(|c| *c != '_' && *c != '-' && *c != ' ')
+        .collect::<String>()
+        .to_lowercase();
+    let color = match normalized.as_str() {
+        "reset" | "default" => Color::Reset,
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "gray" | "grey" => Color::Gray,
+        "darkgray" | "darkgrey" => Color::DarkGray,
+        "lightred" => Color::LightRed,
+        "lightgreen" => Color::LightGreen,
+        "lightyellow" => Color::LightYellow,
+        "lightblue" => Color::LightBlue,
+        "lightmagenta" => Color::LightMagenta,
+        "lightcyan" => Color::LightCyan,
+        "white" => Color::White,
+        _ => return None,
+    };
+    Some(color)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_color_names_and_hex() {
+        assert_eq!(parse_color("light_cyan"), Some(Color::LightCyan));
+        assert_eq!(parse_color("DarkGrey"), Some(Color::DarkGray));
+        assert_eq!(parse_color("#d75f00"), Some(Color::Rgb(0xd7, 0x5f, 0x00)));
+        assert_eq!(parse_color("#d75f0"), None);
+        assert_eq!(parse_color("#zz5f00"), None);
+        assert_eq!(parse_color("mauve"), None);
+    }
+
+    #[test]
+    fn applies_configured_palette_and_colors() {
+        let config = ThemeConfig {
+            name: Some("light".to_string()),
+            author_palette: Some(vec!["blue".to_string()]),
+            colors: HashMap::from([("border".to_string(), "red".to_string())]),
+        };
+        let theme = Theme::from_config(&config).unwrap();
+        assert_eq!(theme.author_palette, vec![Color::Blue]);
+        assert_eq!(theme.author_color("anyone"), Color::Blue);
+        assert_eq!(theme.border.fg, Some(Color::Red));
+    }
+
+    #[test]
+    fn rejects_unknown_themes_and_slots() {
+        let unknown_theme = ThemeConfig {
+            name: Some("solarized".to_string()),
+            ..ThemeConfig::default()
+        };
+        assert!(Theme::from_config(&unknown_theme).is_err());
+        let unknown_slot = ThemeConfig {
+            colors: HashMap::from([("sidebar".to_string(), "red".to_string())]),
+            ..ThemeConfig::default()
+        };
+        assert!(Theme::from_config(&unknown_slot).is_err());
+    }
+
+    #[test]
+    fn author_colors_are_stable() {
+        let theme = Theme::dark();
+        assert_eq!(theme.author_color("alice"), theme.author_color("alice"));
+        assert!(theme.author_palette.contains(&theme.author_color("bob")));
+    }
+}
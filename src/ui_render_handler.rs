@@ -1,15 +1,22 @@
 use std::vec;
 
-use crate::app_inputs::{App, InputMode, LoginInput};
+use crate::app_inputs::{App, InputMode, LoginInput, Pane};
+use crate::e2e::{self, KeyStatus};
+use crate::events::wiggles_event::PresenceStatus;
+use crate::keymap::Action;
+use crate::markdown_handler::BodyRenderer;
 use crate::model::models::Message;
+use crate::rooms;
+use crate::search::{self, Search};
+use crate::theme::Theme;
 use chrono::prelude::*;
 
 use tui::{
     backend::Backend,
-    layout::{Alignment, Constraint, Direction, Layout},
-    style::{Color, Modifier, Style},
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
+    style::{Modifier, Style},
     text::{Span, Spans, Text},
-    widgets::{Block, BorderType, Borders, List, ListItem, Paragraph},
+    widgets::{Block, BorderType, Borders, List, ListItem, ListState, Paragraph},
     Frame,
 };
 use unicode_width::UnicodeWidthStr;
@@ -17,9 +24,10 @@ use unicode_width::UnicodeWidthStr;
 //chunk array
 // 0 = top text
 // 1 = input box
-// 2 = messages
+// 2 = rooms, messages and members panes
 // 3 = copyright
 pub fn ui<B: Backend>(f: &mut Frame<B>, app: &App) {
+    let theme = &app.theme;
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .margin(2)
@@ -33,29 +41,24 @@ pub fn ui<B: Backend>(f: &mut Frame<B>, app: &App) {
             .as_ref(),
         )
         .split(f.size());
+    let submit_key = key_label(app, Action::Submit);
     let (msg, style) = match app.input_mode {
         InputMode::Normal => (
-            vec![
-                Span::raw("  📟 Press "),
-                Span::styled("q", Style::default().add_modifier(Modifier::BOLD)),
-                Span::raw(" to exit, Press "),
-                Span::styled("e", Style::default().add_modifier(Modifier::BOLD)),
-                Span::raw(" to start editing. Press "),
-                Span::styled("l", Style::default().add_modifier(Modifier::BOLD)),
-                Span::raw(" to login. This is walkie talkie wiggles. 📟"),
-            ],
-            Style::default().add_modifier(Modifier::RAPID_BLINK),
+            [
+                vec![Span::raw("  📟 ")],
+                help_spans(theme, app.keymap.help(&NORMAL_HELP)),
+                vec![Span::raw(". This is walkie talkie wiggles. 📟")],
+            ]
+            .concat(),
+            theme.help,
         ),
         InputMode::Login => match app.login_input_mode {
             LoginInput::UserName => (
                 vec![
                     Span::raw("Enter "),
-                    Span::styled(
-                        "your username ",
-                        Style::default().add_modifier(Modifier::BOLD),
-                    ),
+                    Span::styled("your username ", theme.help_key),
                     Span::raw("when you are finished, press "),
-                    Span::styled("Enter", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::styled(submit_key, theme.help_key),
                     Span::raw("  📟"),
                 ],
                 Style::default(),
@@ -64,25 +67,54 @@ pub fn ui<B: Backend>(f: &mut Frame<B>, app: &App) {
             LoginInput::Password => (
                 vec![
                     Span::raw("Enter "),
-                    Span::styled(
-                        "your password ",
-                        Style::default().add_modifier(Modifier::BOLD),
-                    ),
+                    Span::styled("your password ", theme.help_key),
                     Span::raw("when you are finished, press "),
-                    Span::styled("Enter", Style::default().add_modifier(Modifier::BOLD)),
+                    Span::styled(submit_key, theme.help_key),
                     Span::raw("  📟"),
                 ],
                 Style::default(),
             ),
+            LoginInput::NewPassphrase | LoginInput::RepeatPassphrase => (
+                vec![
+                    Span::raw("Enter "),
+                    Span::styled(
+                        match app.login_input_mode {
+                            LoginInput::NewPassphrase => "a new passphrase ",
+                            _ => "the passphrase again ",
+                        },
+                        theme.help_key,
+                    ),
+                    Span::raw("for the credential store, press "),
+                    Span::styled(submit_key, theme.help_key),
+                    Span::raw(" when you are finished or "),
+                    Span::styled(key_label(app, Action::Cancel), theme.help_key),
+                    Span::raw(" to skip direct messages  📟"),
+                ],
+                Style::default(),
+            ),
         },
 
         InputMode::Editing => (
+            [
+                vec![Span::raw("  ")],
+                help_spans(theme, app.keymap.help(&EDITING_HELP)),
+                vec![Span::raw(" 📟")],
+            ]
+            .concat(),
+            Style::default(),
+        ),
+
+        InputMode::Search => (
             vec![
-                Span::raw("  Press "),
-                Span::styled("Esc", Style::default().add_modifier(Modifier::BOLD)),
-                Span::raw(" to stop editing, "),
-                Span::styled("Enter", Style::default().add_modifier(Modifier::BOLD)),
-                Span::raw(" to record the message 📟"),
+                Span::raw("Search messages, press "),
+                Span::styled(submit_key, theme.help_key),
+                Span::raw(" to search or open a result, "),
+                Span::styled(key_label(app, Action::NextResult), theme.help_key),
+                Span::raw("/"),
+                Span::styled(key_label(app, Action::PreviousResult), theme.help_key),
+                Span::raw(" to pick one, "),
+                Span::styled(key_label(app, Action::Cancel), theme.help_key),
+                Span::raw(" to go back  📟"),
             ],
             Style::default(),
         ),
@@ -92,17 +124,27 @@ pub fn ui<B: Backend>(f: &mut Frame<B>, app: &App) {
     let help_message = Paragraph::new(text);
     f.render_widget(help_message, chunks[0]);
 
-    let input = Paragraph::new(app.input.as_ref())
+    let input_text = match (&app.input_mode, &app.login_input_mode) {
+        (InputMode::Login, LoginInput::UserName) => app.input.to_string(),
+        // the password is never drawn, only how long it is
+        (InputMode::Login, _) => "•".repeat(app.password.chars().count()),
+        _ => app.input.to_string(),
+    };
+    let input = Paragraph::new(input_text.as_str())
         .style(match app.input_mode {
-            InputMode::Normal => Style::default(),
-            InputMode::Editing => Style::default().fg(Color::Yellow),
-            InputMode::Login => Style::default().fg(Color::Yellow),
+            InputMode::Normal => theme.input,
+            InputMode::Editing => theme.input_active,
+            InputMode::Login => theme.input_active,
+            InputMode::Search => theme.input_active,
         })
         .block(
             Block::default()
                 .borders(Borders::ALL)
-                .title("Input")
-                .style(Style::default().fg(Color::LightBlue)),
+                .title(match app.input_mode {
+                    InputMode::Search => "Search",
+                    _ => "Input",
+                })
+                .style(theme.border),
         );
     f.render_widget(input, chunks[1]);
 
@@ -112,12 +154,12 @@ pub fn ui<B: Backend>(f: &mut Frame<B>, app: &App) {
             // Make the cursor visible and ask tui-rs to put it at the specified coordinates after rendering
             f.set_cursor(
                 // Put cursor past the end of the input text
-                chunks[1].x + app.input.width() as u16 + 1,
+                chunks[1].x + input_text.width() as u16 + 1,
                 // Move one line down, from the border to the input line
                 chunks[1].y + 1,
             )
         }
-        InputMode::Editing => {
+        InputMode::Editing | InputMode::Search => {
             // Make the cursor visible and ask tui-rs to put it at the specified coordinates after rendering
             f.set_cursor(
                 // Put cursor past the end of the input text
@@ -127,35 +169,454 @@ pub fn ui<B: Backend>(f: &mut Frame<B>, app: &App) {
             )
         }
     }
-    let messages: Vec<ListItem> = app
-        .messages
+    let show_home = app.current_user.is_empty() || matches!(app.input_mode, InputMode::Login);
+    match show_home {
+        true => f.render_widget(render_home(app), chunks[2]),
+        false => render_panes(f, app, chunks[2]),
+    }
+
+    let copyright = render_copyright(theme);
+    f.render_widget(copyright, chunks[3]);
+}
+
+const NORMAL_HELP: [Action; 6] = [
+    Action::Quit,
+    Action::StartEditing,
+    Action::Login,
+    Action::FocusNext,
+    Action::ToggleMembers,
+    Action::Search,
+];
+const EDITING_HELP: [Action; 3] = [Action::Cancel, Action::Submit, Action::CompleteMention];
+
+// "Press <key> <description>, <key> <description>" for the bound actions
+fn help_spans<'a>(theme: &Theme, help: Vec<(String, &'static str)>) -> Vec<Span<'a>> {
+    let mut spans = vec![Span::raw("Press ")];
+    for (i, (key, description)) in help.into_iter().enumerate() {
+        if i > 0 {
+            spans.push(Span::raw(", "));
+        }
+        spans.push(Span::styled(key, theme.help_key));
+        spans.push(Span::raw(format!(" {}", description)));
+    }
+    spans
+}
+
+fn key_label(app: &App, action: Action) -> String {
+    app.keymap
+        .key_for(action)
+        .map_or_else(|| "(unbound)".to_string(), |key| key.to_string())
+}
+
+const SIDEBAR_WIDTH: u16 = 24;
+// below this width only one sidebar fits next to the messages
+const WIDE_LAYOUT_WIDTH: u16 = 100;
+// below this width only the focused pane is shown
+const NARROW_LAYOUT_WIDTH: u16 = 60;
+
+struct PaneAreas {
+    rooms: Option<Rect>,
+    messages: Option<Rect>,
+    members: Option<Rect>,
+}
+
+fn pane_areas(area: Rect, app: &App) -> PaneAreas {
+    if area.width < NARROW_LAYOUT_WIDTH {
+        let only = |pane: Pane| (app.focus == pane).then_some(area);
+        return PaneAreas {
+            rooms: only(Pane::Rooms),
+            messages: only(Pane::Messages),
+            members: only(Pane::Members),
+        };
+    }
+    let show_members = match area.width < WIDE_LAYOUT_WIDTH {
+        true => app.show_members && app.focus == Pane::Members,
+        false => app.show_members,
+    };
+    let show_rooms = area.width >= WIDE_LAYOUT_WIDTH || !show_members;
+
+    let mut constraints = Vec::new();
+    if show_rooms {
+        constraints.push(Constraint::Length(SIDEBAR_WIDTH));
+    }
+    constraints.push(Constraint::Min(0));
+    if show_members {
+        constraints.push(Constraint::Length(SIDEBAR_WIDTH));
+    }
+    let mut chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(constraints)
+        .split(area)
+        .into_iter();
+    PaneAreas {
+        rooms: show_rooms.then(|| chunks.next()).flatten(),
+        messages: chunks.next(),
+        members: show_members.then(|| chunks.next()).flatten(),
+    }
+}
+
+fn render_panes<B: Backend>(f: &mut Frame<B>, app: &App, area: Rect) {
+    let areas = pane_areas(area, app);
+    if let Some(area) = areas.rooms {
+        f.render_widget(render_rooms(app), area);
+    }
+    match (areas.messages, &app.search) {
+        (Some(area), Some(search)) => render_search(f, app, search, area),
+        (Some(area), None) => render_message_list(f, app, area),
+        (None, _) => {}
+    }
+    if let Some(area) = areas.members {
+        f.render_widget(render_members(app), area);
+    }
+}
+
+// The open room's messages with the typing indicator under them
+fn render_message_list<B: Backend>(f: &mut Frame<B>, app: &App, area: Rect) {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(0), Constraint::Length(1)].as_ref())
+        .split(area);
+    let (area, status_area) = (chunks[0], chunks[1]);
+    f.render_widget(render_status_line(app), status_area);
+
+    let items = render_messages(app);
+    // selecting the newest message keeps the list scrolled to the bottom,
+    // unless a search result was opened
+    let jumped_to = app
+        .jump_to
+        .and_then(|id| app.room_messages().position(|m| m.id == id));
+    let mut state = ListState::default();
+    state.select(jumped_to.or_else(|| items.len().checked_sub(1)));
+    let title = messages_title(app);
+    let messages =
+        List::new(items)
+            .style(app.theme.text)
+            .block(pane_block(app, Pane::Messages, title));
+    f.render_stateful_widget(messages, area, &mut state);
+}
+
+// Results, or the messages around the opened result, in place of the message pane
+fn render_search<B: Backend>(f: &mut Frame<B>, app: &App, search: &Search, area: Rect) {
+    let theme = &app.theme;
+    let terms = search.terms();
+    let line = |m: &Message| {
+        let body = m.body.replace('\n', " ");
+        let mut spans = vec![
+            Span::styled(
+                m.name.to_string(),
+                Style::default()
+                    .fg(theme.author_color(&m.name))
+                    .add_modifier(Modifier::BOLD),
+            ),
+            Span::raw(": "),
+        ];
+        spans.extend(highlight(&body, &terms, theme));
+        spans
+    };
+    let mut state = ListState::default();
+    let (items, title): (Vec<ListItem>, String) = match (&search.context, search.selected_result())
+    {
+        (Some(context), Some(result)) => {
+            state.select(context.iter().position(|m| m.id == result.id));
+            let items = context
+                .iter()
+                .map(|m| ListItem::new(Spans::from(line(m))))
+                .collect();
+            let room = rooms::display_name(&result.room, &app.current_user);
+            (items, format!("Around the result in {}", room))
+        }
+        _ => {
+            state.select((!search.results.is_empty()).then_some(search.selected));
+            let items = search
+                .results
+                .iter()
+                .map(|m| {
+                    let room = rooms::display_name(&m.room, &app.current_user);
+                    let mut spans = vec![Span::styled(format!("{} ", room), theme.hint)];
+                    spans.extend(line(m));
+                    ListItem::new(Spans::from(spans))
+                })
+                .collect();
+            let title = match (search.query.is_empty(), search.results.len()) {
+                (true, _) => "Search".to_string(),
+                (false, 0) => format!("No messages match \"{}\"", search.query),
+                (false, count) => format!("{} results for \"{}\"", count, search.query),
+            };
+            (items, title)
+        }
+    };
+    let list = List::new(items)
+        .style(theme.text)
+        .highlight_style(theme.selected)
+        .block(pane_block(app, Pane::Messages, title));
+    f.render_stateful_widget(list, area, &mut state);
+}
+
+// Splits text into spans, styling the words that match the search terms
+fn highlight<'a>(text: &str, terms: &[String], theme: &Theme) -> Vec<Span<'a>> {
+    let mut spans = Vec::new();
+    let mut end = 0;
+    for range in search::matches(text, terms) {
+        if range.start > end {
+            spans.push(Span::raw(text[end..range.start].to_string()));
+        }
+        spans.push(Span::styled(
+            text[range.clone()].to_string(),
+            theme.search_match,
+        ));
+        end = range.end;
+    }
+    if end < text.len() {
+        spans.push(Span::raw(text[end..].to_string()));
+    }
+    spans
+}
+
+// "alice is typing…" under the message pane
+fn render_status_line<'a>(app: &App) -> Paragraph<'a> {
+    if let Some(notice) = &app.notice {
+        return Paragraph::new(notice.to_string()).style(app.theme.unread);
+    }
+    let typing = app.typing.typing_in(&app.current_room, &app.current_user);
+    let text = match typing.as_slice() {
+        [] => String::new(),
+        [name] => format!("{} is typing…", name),
+        [first, second] => format!("{} and {} are typing…", first, second),
+        _ => "Several people are typing…".to_string(),
+    };
+    Paragraph::new(text).style(app.theme.status)
+}
+
+fn pane_block<'a>(app: &App, pane: Pane, title: String) -> Block<'a> {
+    let style = match app.focus == pane {
+        true => app.theme.focus,
+        false => app.theme.block,
+    };
+    Block::default()
+        .borders(Borders::ALL)
+        .style(style)
+        .title(title)
+}
+
+// direct messages say whether the other participant's key has been verified
+fn messages_title(app: &App) -> String {
+    let name = rooms::display_name(&app.current_room, &app.current_user);
+    let status = match app.current_peer().map(|peer| app.key_status(peer)) {
+        None => return name,
+        Some(KeyStatus::Missing) => "no key yet",
+        Some(KeyStatus::Unverified) => "🔒 encrypted, /keys to verify",
+        Some(KeyStatus::Verified) => "🔒 verified",
+        Some(KeyStatus::Changed) => "⚠ key changed, /keys to compare",
+    };
+    format!("{} · {}", name, status)
+}
+
+// names the profile once there is more than one to switch between
+fn rooms_title(app: &App) -> String {
+    match app.profiles.len() {
+        0 | 1 => "Rooms".to_string(),
+        _ => format!("Rooms · {}", app.profiles.active_name()),
+    }
+}
+
+fn render_rooms<'a>(app: &App) -> List<'a> {
+    let theme = &app.theme;
+    let items: Vec<ListItem> = app
+        .visible_rooms()
+        .into_iter()
+        .map(|room| {
+            let name = rooms::display_name(room, &app.current_user);
+            // muted rooms keep their count but don't stand out
+            let unread_style = match app.notifier.is_muted(room) {
+                true => theme.hint,
+                false => theme.unread,
+            };
+            let line = match app.unread_count(room) {
+                0 => Spans::from(Span::raw(name)),
+                count => Spans::from(vec![
+                    Span::styled(name, unread_style),
+                    Span::styled(format!(" ({})", count), unread_style),
+                ]),
+            };
+            let item = ListItem::new(line);
+            match *room == app.current_room {
+                true => item.style(theme.selected),
+                false => item,
+            }
+        })
+        .collect();
+    List::new(items)
+        .style(theme.text)
+        .block(pane_block(app, Pane::Rooms, rooms_title(app)))
+}
+
+// Online members first, each with a status marker
+fn render_members<'a>(app: &App) -> List<'a> {
+    let theme = &app.theme;
+    let mut members: Vec<(&String, PresenceStatus)> = app
+        .user_names
         .iter()
-        .enumerate()
-        .map(|(_i, m)| {
-            let content = vec![Spans::from(Span::raw(format!("{}: {}", m.name, m.body)))];
-            ListItem::new(content)
+        .map(|name| (name, app.presence.status(name)))
+        .collect();
+    members.sort_by_key(|(name, status)| (*status, name.to_lowercase()));
+
+    let items: Vec<ListItem> = members
+        .into_iter()
+        .map(|(name, status)| {
+            let (marker, marker_style) = match status {
+                PresenceStatus::Online => ("● ", theme.presence_online),
+                PresenceStatus::Away => ("◐ ", theme.presence_away),
+                PresenceStatus::Offline => ("○ ", theme.presence_offline),
+            };
+            let name_style = match status {
+                PresenceStatus::Offline => theme.presence_offline,
+                _ => Style::default().fg(theme.author_color(name)),
+            };
+            ListItem::new(Spans::from(vec![
+                Span::styled(marker, marker_style),
+                Span::styled(name.to_string(), name_style),
+            ]))
         })
         .collect();
+    List::new(items)
+        .style(theme.text)
+        .block(pane_block(app, Pane::Members, "Members".to_string()))
+}
 
-    let messages = List::new(messages)
-        .style(Style::default().fg(Color::LightCyan))
-        .block(
-            Block::default()
-                .style(Style::default().fg(Color::Blue))
-                .borders(Borders::ALL)
-                .title("Messages"),
-        );
-    match app.input_mode {
-        InputMode::Normal => f.render_widget(render_home(&app), chunks[2]),
-        InputMode::Login => f.render_widget(render_home(&app), chunks[2]),
-        InputMode::Editing => f.render_widget(messages, chunks[2]),
+// Consecutive messages from one author share a single header line,
+// and the logged in user's own messages and mentions of them carry a marker in the gutter.
+fn render_messages<'a>(app: &App) -> Vec<ListItem<'a>> {
+    let theme = &app.theme;
+    let renderer = BodyRenderer {
+        theme,
+        current_user: &app.current_user,
+    };
+    let mut previous: Option<&Message> = None;
+    let mut items: Vec<ListItem> = app
+        .room_messages()
+        .map(|m| {
+            let color = theme.author_color(&m.name);
+            let is_own = !app.current_user.is_empty() && m.name == app.current_user;
+            let is_mention = !is_own && m.mentions(&app.current_user);
+            let mut content = Vec::new();
+            if app.first_unread == Some(m.id) {
+                content.push(Spans::from(Span::styled(UNREAD_DIVIDER, theme.unread)));
+                previous = None;
+            }
+            // an unsigned message always gets its own header, so the warning can't be missed
+            let is_unverified = app.unverified.contains(&m.id);
+            if starts_group(previous, m) || is_unverified {
+                let mut header = vec![Span::styled(
+                    m.name.to_string(),
+                    Style::default().fg(color).add_modifier(Modifier::BOLD),
+                )];
+                if is_own {
+                    header.push(Span::styled(" (you)", Style::default().fg(color)));
+                }
+                if is_unverified {
+                    header.push(Span::styled(UNVERIFIED_MARKER, theme.unread));
+                }
+                content.push(Spans::from(header));
+            }
+            previous = match is_unverified {
+                true => None,
+                false => Some(m),
+            };
+
+            let gutter = if is_own {
+                Span::styled(OWN_MESSAGE_GUTTER, Style::default().fg(color))
+            } else if is_mention {
+                Span::styled(MENTION_GUTTER, theme.mention_marker)
+            } else {
+                Span::raw(MESSAGE_GUTTER)
+            };
+            for mut line in renderer.parse_body(&m.body) {
+                line.0.insert(0, gutter.clone());
+                content.push(line);
+            }
+            match app.jump_to == Some(m.id) {
+                true => ListItem::new(content).style(theme.selected),
+                false => ListItem::new(content),
+            }
+        })
+        .collect();
+
+    let seen_by = app.seen_newest();
+    if !seen_by.is_empty() {
+        let text = format!("{}seen by {}", MESSAGE_GUTTER, seen_by.join(", "));
+        items.push(ListItem::new(Span::styled(text, theme.status)));
     }
+    if app.show_keys {
+        items.extend(render_keys(app));
+    }
+    items
+}
 
-    let copyright = render_copyright();
-    f.render_widget(copyright, chunks[3]);
+// Both fingerprints of a direct message, to read out to the other participant
+fn render_keys<'a>(app: &App) -> Vec<ListItem<'a>> {
+    let theme = &app.theme;
+    let peer = match app.current_peer() {
+        Some(peer) => peer,
+        None => return Vec::new(),
+    };
+    let own = app.identity.as_ref().map(|identity| identity.fingerprint());
+    let theirs = app
+        .public_keys
+        .get(peer)
+        .and_then(|key| e2e::fingerprint(key));
+    let status = app.key_status(peer);
+    let hint = match status {
+        KeyStatus::Missing => format!("{} hasn't logged in since keys were added", peer),
+        KeyStatus::Unverified => format!(
+            "Compare both with {} in person or on a call, then type /verify",
+            peer
+        ),
+        KeyStatus::Verified => "Verified, type /keys to hide".to_string(),
+        KeyStatus::Changed => format!(
+            "{}'s key changed since you verified it, compare again and type /verify",
+            peer
+        ),
+    };
+    let lines = [("Your key    ", own), ("Their key   ", theirs)];
+    let mut items: Vec<ListItem> = lines
+        .iter()
+        .map(|(label, fingerprint)| {
+            let fingerprint = fingerprint.as_deref().unwrap_or("none").to_string();
+            ListItem::new(Spans::from(vec![
+                Span::styled(format!("{}{}", MESSAGE_GUTTER, label), theme.status),
+                Span::styled(fingerprint, theme.code),
+            ]))
+        })
+        .collect();
+    items.push(ListItem::new(Span::styled(
+        format!("{}{}", MESSAGE_GUTTER, hint),
+        match status {
+            KeyStatus::Changed => theme.unread,
+            _ => theme.hint,
+        },
+    )));
+    items
+}
+
+const MESSAGE_GUTTER: &str = "  ";
+const UNREAD_DIVIDER: &str = "──── new messages ────";
+const OWN_MESSAGE_GUTTER: &str = "▌ ";
+const MENTION_GUTTER: &str = "@ ";
+const UNVERIFIED_MARKER: &str = " (unverified sender)";
+// messages from one author further apart than this each get a header
+const GROUP_WINDOW_SECONDS: i64 = 5 * 60;
+
+// Whether a message gets a header with its author, rather than continuing the group
+// of the previous one from the same author sent shortly before
+fn starts_group(previous: Option<&Message>, message: &Message) -> bool {
+    previous.is_none_or(|previous| {
+        previous.name != message.name
+            || (message.created_at - previous.created_at).num_seconds() > GROUP_WINDOW_SECONDS
+    })
 }
 
 fn render_home<'a>(app: &App) -> Paragraph<'a> {
+    let theme = &app.theme;
     let home = Paragraph::new(vec![
         Spans::from(vec![Span::raw("")]),
         Spans::from(vec![Span::raw("Walkie 📟")]),
@@ -166,32 +627,35 @@ fn render_home<'a>(app: &App) -> Paragraph<'a> {
         Spans::from(vec![Span::raw("")]),
         Spans::from(vec![Span::styled(
             "Remember to be nice to your friends.",
-            Style::default().fg(Color::LightBlue),
+            theme.tagline,
         )]),
         Spans::from(vec![Span::raw("")]),
         match app.input_mode {
             InputMode::Normal => Spans::from(vec![Span::styled(
-                "Press l to login.",
-                Style::default().fg(Color::White),
+                format!("Press {} to login.", key_label(app, Action::Login)),
+                theme.hint,
             )]),
-            InputMode::Login => Spans::from(vec![Span::styled(
-                "Enter your login deetz above.",
-                Style::default().fg(Color::White),
-            )]),
-            InputMode::Editing => Spans::from(vec![Span::raw("")]),
+            InputMode::Login => match &app.login_error {
+                Some(error) => Spans::from(vec![Span::styled(error.to_string(), theme.unread)]),
+                None => Spans::from(vec![Span::styled(
+                    "Enter your login deetz above.",
+                    theme.hint,
+                )]),
+            },
+            InputMode::Editing | InputMode::Search => Spans::from(vec![Span::raw("")]),
         },
     ])
     .alignment(Alignment::Center)
     .block(
         Block::default()
             .borders(Borders::ALL)
-            .style(Style::default().fg(Color::Blue))
+            .style(theme.block)
             .title("Home")
             .border_type(BorderType::Plain),
     );
     home
 }
-pub fn render_copyright<'a>() -> Paragraph<'a> {
+pub fn render_copyright<'a>(theme: &Theme) -> Paragraph<'a> {
     let get_current_year = || -> String {
         let current_date = chrono::Utc::now();
         let year = current_date.year();
@@ -201,22 +665,24 @@ pub fn render_copyright<'a>() -> Paragraph<'a> {
         "📟 Wiggle-CLI {} - all rights reserved 📟",
         get_current_year()
     ))
-    .style(Style::default().fg(Color::LightCyan))
+    .style(theme.text)
     .alignment(Alignment::Center)
     .block(
         Block::default()
             .borders(Borders::ALL)
-            .style(Style::default().fg(Color::LightBlue))
+            .style(theme.border)
             .title("Copyright")
             .border_type(BorderType::Plain),
     );
     copyright
 }
 
+const MESSAGE_HISTORY_LIMIT: usize = 500;
+
 pub fn remove_old_messages(mut messages: Vec<Message>) -> Vec<Message> {
     let message_count = messages.len();
-    if message_count > 10 {
-        messages.drain(0..message_count - 33);
+    if message_count > MESSAGE_HISTORY_LIMIT {
+        messages.drain(0..message_count - MESSAGE_HISTORY_LIMIT);
     }
     messages
 }
@@ -232,3 +698,55 @@ pub fn remove_old_messages(mut messages: Vec<Message>) -> Vec<Message> {
 //         );
 //     input
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: i32, name: &str, minute: u32) -> Message {
+        Message {
+            id,
+            name: name.to_string(),
+            body: format!("message {}", id),
+            published: true,
+            room: rooms::default_room(),
+            created_at: Utc.with_ymd_and_hms(2024, 5, 1, 9, minute, 0).unwrap(),
+            author_email: format!("{}@example.com", name),
+        }
+    }
+
+    #[test]
+    fn consecutive_messages_from_one_author_are_grouped() {
+        let first = message(1, "alice", 0);
+        assert!(starts_group(None, &first));
+        assert!(!starts_group(Some(&first), &message(2, "alice", 5)));
+        assert!(starts_group(Some(&first), &message(2, "alice", 6)));
+        assert!(starts_group(Some(&first), &message(2, "bob", 0)));
+    }
+
+    // Lines each message takes up, one for its body plus one for a header
+    fn heights(app: &App) -> Vec<usize> {
+        render_messages(app).iter().map(ListItem::height).collect()
+    }
+
+    #[test]
+    fn headers_start_each_group() {
+        let mut app = App {
+            messages: vec![
+                message(1, "alice", 0),
+                message(2, "alice", 1),
+                message(3, "bob", 2),
+                message(4, "bob", 30),
+                message(5, "bob", 31),
+                message(6, "bob", 32),
+            ],
+            ..App::default()
+        };
+        assert_eq!(heights(&app), [2, 1, 2, 2, 1, 1]);
+
+        // the unread divider and unsigned messages break groups up too
+        app.first_unread = Some(5);
+        app.unverified.insert(2);
+        assert_eq!(heights(&app), [2, 2, 2, 2, 3, 1]);
+    }
+}